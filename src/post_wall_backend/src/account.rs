@@ -0,0 +1,63 @@
+use candid::{CandidType, candid_method};
+use ic_cdk_macros::*;
+
+use crate::{registration::{ProfileQuery, get_username, _query_profile, _remove_profile, _suspended_until}, post::{PostQuery, _posts_page, _remove_posts_of}, verification_status::_remove_user, notification::_clear_notifications, relationship::_remove_relationships_of, business::_remove_business_of, delegation::_remove_delegations_of, schedule::_remove_scheduled_posts_of, payment::{lock_deposit, unlock_deposit}, response::{DeleteAccountResponse, ExportDataResponse}};
+
+/// One chunk of a user's data. Every chunk carries the profile; `posts`
+/// holds up to `EXPORT_CHUNK_SIZE` posts and `next_cursor` is where the next
+/// chunk starts, `None` on the last one.
+#[derive(CandidType)]
+pub struct UserDataExport{
+    pub profile: ProfileQuery,
+    pub posts: Vec<PostQuery>,
+    pub next_cursor: Option<u64>,
+}
+
+pub const EXPORT_CHUNK_SIZE: usize = 500;
+
+/// Removes the caller's profile, posts, notifications and block and mute
+/// lists and frees the username. Receipts and badge audit entries are kept
+/// for the treasury.
+#[update]
+#[candid_method(update)]
+pub fn delete_my_account() -> DeleteAccountResponse{
+    let caller = ic_cdk::caller();
+    // leaving and registering again must not lift a suspension or a ban
+    if let Some(until) = _suspended_until(&caller, ic_cdk::api::time()){
+        return DeleteAccountResponse::Suspended{ until }
+    }
+    // a payment in flight would otherwise land on an account that is gone
+    if !lock_deposit(caller){
+        return DeleteAccountResponse::PaymentInProgress
+    }
+    let removed = _remove_profile(&caller);
+    unlock_deposit(&caller);
+    let (username, verification_badge) = match removed{
+        None => return DeleteAccountResponse::UserNotRegistered,
+        Some(removed) => removed
+    };
+    _remove_posts_of(&username);
+    _remove_user(&verification_badge);
+    _clear_notifications(&caller);
+    _remove_relationships_of(&caller);
+    _remove_business_of(&caller);
+    _remove_delegations_of(&caller);
+    _remove_scheduled_posts_of(&caller);
+    DeleteAccountResponse::Success
+}
+
+#[query]
+#[candid_method(query)]
+pub fn export_my_data(cursor: Option<u64>) -> ExportDataResponse{
+    let caller = ic_cdk::caller();
+    let profile = match _query_profile(&caller){
+        None => return ExportDataResponse::UserNotRegistered,
+        Some(profile) => profile
+    };
+    let username = match get_username(&caller){
+        None => return ExportDataResponse::UserNotRegistered,
+        Some(username) => username
+    };
+    let (posts, next_cursor) = _posts_page(&username, cursor.unwrap_or_default(), EXPORT_CHUNK_SIZE);
+    ExportDataResponse::Success(Box::new(UserDataExport{ profile, posts, next_cursor }))
+}
\ No newline at end of file
@@ -0,0 +1,374 @@
+use std::cell::RefCell;
+
+use candid::{CandidType, candid_method, Deserialize, Principal};
+use ic_cdk_macros::*;
+use ic_stable_memory::{SBox, derive::{AsFixedSizeBytes, StableType}, collections::{SHashMap, SVec}};
+
+use crate::{registration::{get_username, get_address, get_verification_badge, _suspended_until}, verification_status::VerificationBadge, post::{CreatePostData, _create_post_for, _edit_post_for}, config::{ProfileLimits, _profile_limits}, rate_limit::_check_rate_limit, utils::{text_length, normalize_text}, response::{BusinessProfileResponse, BusinessMemberResponse, CreatePostResponse, EditPostResponse}};
+
+#[derive(CandidType, AsFixedSizeBytes, StableType, Deserialize, Clone, Copy, PartialEq)]
+pub enum BusinessCategory{
+    Retail,
+    Technology,
+    Media,
+    Finance,
+    Education,
+    Health,
+    Hospitality,
+    NonProfit,
+    Other,
+}
+
+/// Organization details of a `BusinessVerified` account, kept next to the
+/// owner's personal profile.
+#[derive(StableType, AsFixedSizeBytes)]
+pub struct BusinessProfile{
+    pub organization_name: SBox<String>,
+    pub registration_number: SBox<String>,
+    pub website: SBox<String>,
+    pub category: BusinessCategory,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct BusinessProfileData{
+    pub organization_name: String,
+    pub registration_number: String,
+    pub website: String,
+    pub category: BusinessCategory,
+}
+
+impl BusinessProfileData{
+    fn normalized(self) -> Self{
+        Self{
+            organization_name: normalize_text(self.organization_name),
+            registration_number: normalize_text(self.registration_number),
+            website: normalize_text(self.website),
+            ..self
+        }
+    }
+}
+
+impl BusinessProfile{
+    fn new_business_profile(business_profile_data: BusinessProfileData) -> Option<Self>{
+        let organization_name = match SBox::new(business_profile_data.organization_name){
+            Ok(organization_name) => organization_name,
+            Err(_) => return None
+        };
+        let registration_number = match SBox::new(business_profile_data.registration_number){
+            Ok(registration_number) => registration_number,
+            Err(_) => return None
+        };
+        let website = match SBox::new(business_profile_data.website){
+            Ok(website) => website,
+            Err(_) => return None
+        };
+        Some(Self{ organization_name, registration_number, website, category: business_profile_data.category })
+    }
+
+    fn _to_business_profile_data(&self) -> BusinessProfileData{
+        BusinessProfileData{
+            organization_name: self.organization_name.clone(),
+            registration_number: self.registration_number.clone(),
+            website: self.website.clone(),
+            category: self.category,
+        }
+    }
+}
+
+/// What a member may do on the business account's behalf.
+#[derive(CandidType, AsFixedSizeBytes, StableType, Deserialize, Clone, Copy)]
+pub struct MemberPermissions{
+    pub can_create_posts: bool,
+    pub can_edit_posts: bool,
+}
+
+#[derive(StableType, AsFixedSizeBytes)]
+pub struct BusinessMember{
+    pub address: Principal,
+    pub permissions: MemberPermissions,
+}
+
+#[derive(CandidType)]
+pub struct BusinessMemberQuery{
+    pub username: String,
+    pub permissions: MemberPermissions,
+}
+
+#[derive(StableType, AsFixedSizeBytes)]
+pub struct BusinessState{
+    pub profiles: SHashMap<Principal, BusinessProfile>,
+    pub members: SHashMap<Principal, SVec<BusinessMember>>,
+}
+
+impl Default for BusinessState{
+    fn default() -> Self {
+        Self{
+            profiles: SHashMap::new(),
+            members: SHashMap::new(),
+        }
+    }
+}
+
+impl BusinessState{
+    fn set_profile(&mut self, owner: Principal, business_profile: BusinessProfile) -> bool{
+        self.profiles.insert(owner, business_profile).is_ok()
+    }
+
+    fn profile(&self, owner: &Principal) -> Option<BusinessProfileData>{
+        self.profiles.get(owner).map(|business_profile| business_profile._to_business_profile_data())
+    }
+
+    /// Adds `member` to `owner`'s organization, or replaces the permissions
+    /// of an existing member.
+    fn set_member(&mut self, owner: Principal, member: Principal, permissions: MemberPermissions) -> bool{
+        if !self.members.contains_key(&owner) && self.members.insert(owner, SVec::new()).is_err(){
+            return false
+        }
+        let mut members = match self.members.get_mut(&owner){
+            None => return false,
+            Some(members) => members
+        };
+        for index in 0..members.len(){
+            if let Some(mut existing) = members.get_mut(index){
+                if existing.address == member{
+                    existing.permissions = permissions;
+                    return true
+                }
+            }
+        }
+        members.push(BusinessMember{ address: member, permissions }).is_ok()
+    }
+
+    fn remove_member(&mut self, owner: &Principal, member: &Principal){
+        if let Some(mut members) = self.members.get_mut(owner){
+            if let Some(index) = members.iter().position(|existing| existing.address == *member){
+                members.remove(index);
+            }
+        }
+    }
+
+    fn permissions(&self, owner: &Principal, member: &Principal) -> Option<MemberPermissions>{
+        self.members.get(owner)?.iter().find(|existing| existing.address == *member).map(|existing| existing.permissions)
+    }
+
+    fn members(&self, owner: &Principal) -> Vec<(Principal, MemberPermissions)>{
+        match self.members.get(owner){
+            None => Vec::new(),
+            Some(members) => members.iter().map(|member| (member.address, member.permissions)).collect()
+        }
+    }
+
+    /// Drops the organization `address` owns and its memberships in others.
+    fn remove_business_of(&mut self, address: &Principal){
+        self.profiles.remove(address);
+        self.members.remove(address);
+        let owners: Vec<Principal> = self.members.iter().map(|(owner, _)| *owner).collect();
+        for owner in owners{
+            self.remove_member(&owner, address);
+        }
+    }
+}
+
+thread_local! {
+    pub static BUSINESS_STATE: RefCell<BusinessState> = RefCell::default();
+}
+
+pub(crate) fn _remove_business_of(address: &Principal){
+    BUSINESS_STATE.with(|state| state.borrow_mut().remove_business_of(address))
+}
+
+fn business_profile_check(business_profile_data: &BusinessProfileData, limits: &ProfileLimits) -> Result<(), BusinessProfileResponse>{
+    let website = business_profile_data.website.as_str();
+    if text_length(&business_profile_data.organization_name) > limits.max_organization_name_length{
+        Err(BusinessProfileResponse::OrganizationNameTooLong)
+    }else if text_length(&business_profile_data.registration_number) > limits.max_registration_number_length{
+        Err(BusinessProfileResponse::RegistrationNumberTooLong)
+    }else if text_length(website) > limits.max_website_length{
+        Err(BusinessProfileResponse::WebsiteTooLong)
+    }else if !website.is_empty() && !website.starts_with("https://") && !website.starts_with("http://"){
+        Err(BusinessProfileResponse::InvalidWebsite)
+    }else{
+        Ok(())
+    }
+}
+
+/// The business account `business` names, if `member` may act for it with
+/// the permission `allowed` picks. The business must still hold its badge.
+fn _business_for(member: &Principal, business: &str, allowed: fn(&MemberPermissions) -> bool) -> Option<Principal>{
+    let owner = get_address(business)?;
+    if get_verification_badge(&owner) != Some(VerificationBadge::BusinessVerified){
+        return None
+    }
+    let permissions = BUSINESS_STATE.with(|state| state.borrow().permissions(&owner, member))?;
+    if allowed(&permissions){ Some(owner) }else{ None }
+}
+
+#[update]
+#[candid_method(update)]
+pub fn set_business_profile(business_profile_data: BusinessProfileData) -> BusinessProfileResponse{
+    let caller = ic_cdk::caller();
+    if let Err(retry_after_ns) = _check_rate_limit(caller, "update_profile"){
+        return BusinessProfileResponse::RateLimited{ retry_after_ns }
+    }
+    match get_verification_badge(&caller){
+        None => return BusinessProfileResponse::UserNotRegistered,
+        Some(VerificationBadge::BusinessVerified) => {},
+        Some(_) => return BusinessProfileResponse::NotBusinessAccount
+    }
+    if let Some(until) = _suspended_until(&caller, ic_cdk::api::time()){
+        return BusinessProfileResponse::Suspended{ until }
+    }
+    let business_profile_data = business_profile_data.normalized();
+    if let Err(e) = business_profile_check(&business_profile_data, &_profile_limits()){
+        return e
+    }
+    let business_profile = match BusinessProfile::new_business_profile(business_profile_data){
+        None => return BusinessProfileResponse::FailedToAllocateMemory,
+        Some(business_profile) => business_profile
+    };
+    if !BUSINESS_STATE.with(|state| state.borrow_mut().set_profile(caller, business_profile)){
+        return BusinessProfileResponse::FailedToAllocateMemory
+    }
+    BusinessProfileResponse::Success
+}
+
+#[query]
+#[candid_method(query)]
+pub fn get_business_profile(username: String) -> Option<BusinessProfileData>{
+    let owner = get_address(&username)?;
+    BUSINESS_STATE.with(|state| state.borrow().profile(&owner))
+}
+
+/// Lets the registered user `username` act for the caller's business with
+/// `permissions`; calling it again for a member changes their permissions.
+#[update]
+#[candid_method(update)]
+pub fn add_business_member(username: String, permissions: MemberPermissions) -> BusinessMemberResponse{
+    let caller = ic_cdk::caller();
+    if let Err(retry_after_ns) = _check_rate_limit(caller, "add_business_member"){
+        return BusinessMemberResponse::RateLimited{ retry_after_ns }
+    }
+    match get_verification_badge(&caller){
+        None => return BusinessMemberResponse::UserNotRegistered,
+        Some(VerificationBadge::BusinessVerified) => {},
+        Some(_) => return BusinessMemberResponse::NotBusinessAccount
+    }
+    if let Some(until) = _suspended_until(&caller, ic_cdk::api::time()){
+        return BusinessMemberResponse::Suspended{ until }
+    }
+    let member = match get_address(&username){
+        None => return BusinessMemberResponse::UserNotFound,
+        Some(member) => member
+    };
+    if member == caller{
+        return BusinessMemberResponse::CannotTargetYourself
+    }
+    if !BUSINESS_STATE.with(|state| state.borrow_mut().set_member(caller, member, permissions)){
+        return BusinessMemberResponse::FailedToAllocateMemory
+    }
+    BusinessMemberResponse::Success
+}
+
+#[update]
+#[candid_method(update)]
+pub fn remove_business_member(username: String) -> BusinessMemberResponse{
+    let caller = ic_cdk::caller();
+    if get_username(&caller).is_none(){
+        return BusinessMemberResponse::UserNotRegistered
+    }
+    let member = match get_address(&username){
+        None => return BusinessMemberResponse::UserNotFound,
+        Some(member) => member
+    };
+    BUSINESS_STATE.with(|state| state.borrow_mut().remove_member(&caller, &member));
+    BusinessMemberResponse::Success
+}
+
+/// Members of the caller's business, in the order they were added.
+#[query]
+#[candid_method(query)]
+pub fn get_business_members() -> Vec<BusinessMemberQuery>{
+    let caller = ic_cdk::caller();
+    BUSINESS_STATE.with(|state| state.borrow().members(&caller))
+        .into_iter()
+        .filter_map(|(address, permissions)| get_username(&address).map(|username| BusinessMemberQuery{ username, permissions }))
+        .collect()
+}
+
+/// Publishes a post on the business account `business`, under its badge and
+/// limits, on behalf of a member allowed to create posts.
+#[update]
+#[candid_method(update)]
+pub fn create_post_as(business: String, create_post_data: CreatePostData) -> CreatePostResponse{
+    let caller = ic_cdk::caller();
+    if let Err(retry_after_ns) = _check_rate_limit(caller, "create_post"){
+        return CreatePostResponse::RateLimited{ retry_after_ns }
+    }
+    if let Some(until) = _suspended_until(&caller, ic_cdk::api::time()){
+        return CreatePostResponse::Suspended{ until }
+    }
+    let owner = match _business_for(&caller, &business, |permissions| permissions.can_create_posts){
+        None => return CreatePostResponse::NotAuthorized,
+        Some(owner) => owner
+    };
+    _create_post_for(owner, Some(caller), create_post_data)
+}
+
+/// Edits a post of the business account `business` on behalf of a member
+/// allowed to edit posts.
+#[update]
+#[candid_method(update)]
+pub fn edit_post_as(business: String, post_id: u64, create_post_data: CreatePostData) -> EditPostResponse{
+    let caller = ic_cdk::caller();
+    if let Err(retry_after_ns) = _check_rate_limit(caller, "edit_post"){
+        return EditPostResponse::RateLimited{ retry_after_ns }
+    }
+    if let Some(until) = _suspended_until(&caller, ic_cdk::api::time()){
+        return EditPostResponse::Suspended{ until }
+    }
+    let owner = match _business_for(&caller, &business, |permissions| permissions.can_edit_posts){
+        None => return EditPostResponse::NotAuthorized,
+        Some(owner) => owner
+    };
+    _edit_post_for(owner, post_id, create_post_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::testing::{principal, init_memory};
+
+    const POSTER: MemberPermissions = MemberPermissions{ can_create_posts: true, can_edit_posts: false };
+    const EDITOR: MemberPermissions = MemberPermissions{ can_create_posts: true, can_edit_posts: true };
+
+    fn business_data(website: &str) -> BusinessProfileData {
+        BusinessProfileData{ organization_name: "Acme Ltd".to_string(), registration_number: "12345".to_string(), website: website.to_string(), category: BusinessCategory::Retail }
+    }
+
+    #[test]
+    fn members_keep_one_entry_with_the_latest_permissions() {
+        init_memory();
+        let mut state = BusinessState::default();
+        assert!(state.set_member(principal(1), principal(2), POSTER));
+        assert!(state.set_member(principal(1), principal(3), POSTER));
+        assert!(state.set_member(principal(1), principal(2), EDITOR));
+        assert_eq!(state.members(&principal(1)).len(), 2);
+        assert!(state.permissions(&principal(1), &principal(2)).is_some_and(|permissions| permissions.can_edit_posts));
+        assert!(state.permissions(&principal(4), &principal(2)).is_none());
+        state.remove_business_of(&principal(2));
+        assert!(state.permissions(&principal(1), &principal(2)).is_none());
+        state.remove_member(&principal(1), &principal(3));
+        assert!(state.members(&principal(1)).is_empty());
+    }
+
+    #[test]
+    fn business_profiles_are_checked_against_the_limits() {
+        let limits = Config::default().profile_limits;
+        assert!(business_profile_check(&business_data("https://acme.example"), &limits).is_ok());
+        assert!(business_profile_check(&business_data(""), &limits).is_ok());
+        assert!(matches!(business_profile_check(&business_data("acme.example"), &limits), Err(BusinessProfileResponse::InvalidWebsite)));
+        let long_name = BusinessProfileData{ organization_name: "a".repeat(101), ..business_data("") };
+        assert!(matches!(business_profile_check(&long_name, &limits), Err(BusinessProfileResponse::OrganizationNameTooLong)));
+    }
+}
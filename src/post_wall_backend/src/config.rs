@@ -0,0 +1,256 @@
+use std::cell::RefCell;
+
+use candid::{CandidType, candid_method, Deserialize, Nat};
+use ic_cdk_macros::*;
+use ic_stable_memory::derive::{AsFixedSizeBytes, StableType};
+
+use crate::{response::UpdateStateResponse, init::is_this_caller_authority, verification_status::VerificationBadge};
+
+/// Name lengths in Unicode scalar values, counted after NFC normalization.
+/// Usernames are ASCII, so theirs is also a byte count. The `organization`,
+/// `registration_number` and `website` limits apply to business profiles.
+#[derive(CandidType, AsFixedSizeBytes, StableType, Deserialize, Clone, Copy)]
+pub struct ProfileLimits{
+    pub max_first_name_length: u64,
+    pub max_middle_name_length: u64,
+    pub max_last_name_length: u64,
+    pub max_username_length: u64,
+    pub max_organization_name_length: u64,
+    pub max_registration_number_length: u64,
+    pub max_website_length: u64,
+}
+
+/// Topic and message lengths in Unicode scalar values, counted after NFC
+/// normalization.
+#[derive(CandidType, AsFixedSizeBytes, StableType, Deserialize, Clone, Copy)]
+pub struct ContentLimits{
+    pub max_topic_length: u64,
+    pub max_message_length: u64,
+    pub max_links: u64,
+}
+
+/// Token bucket allowing bursts of `capacity` calls per endpoint, with one
+/// call coming back every `refill_interval` nanoseconds. A `refill_interval`
+/// of `0` disables the limit.
+#[derive(CandidType, AsFixedSizeBytes, StableType, Deserialize, Clone, Copy)]
+pub struct RateLimit{
+    pub capacity: u64,
+    pub refill_interval: u64,
+}
+
+/// What an account holding a given `VerificationBadge` is allowed to do.
+/// `edit_window` is in nanoseconds after `posted_at`; `0` disables editing.
+/// `max_pinned_posts` of `0` disables pinning.
+#[derive(CandidType, AsFixedSizeBytes, StableType, Deserialize, Clone, Copy)]
+pub struct BadgeCapabilities{
+    pub posts_per_day: u64,
+    pub max_pinned_posts: u64,
+    pub edit_window: u64,
+    pub can_attach_links: bool,
+    pub can_schedule_posts: bool,
+    pub rate_limit: RateLimit,
+}
+
+/// Limits enforced by the canister. `None` for a badge override means the
+/// badge uses `content_limits`. `verification_period` is how long a paid
+/// verification lasts, `expiry_notice_period` how long before expiry the
+/// holder is notified and `refund_grace_period` how long after paying a
+/// verification fee it can still be refunded, all in nanoseconds.
+/// `tip_fee_basis_points` is the share of every tip kept by the platform.
+/// A post repeating a message its author posted less than
+/// `duplicate_window` nanoseconds earlier is rejected; `0` allows repeats.
+/// `minimum_age` is in whole years on the day of registration. An account can
+/// have at most `max_scheduled_posts` posts waiting to be published, each no
+/// more than `max_schedule_ahead` nanoseconds in the future.
+#[derive(CandidType, AsFixedSizeBytes, StableType, Deserialize, Clone, Copy)]
+pub struct Config{
+    pub profile_limits: ProfileLimits,
+    pub content_limits: ContentLimits,
+    pub plus_verified_content_limits: Option<ContentLimits>,
+    pub business_verified_content_limits: Option<ContentLimits>,
+    pub regular_verified_capabilities: BadgeCapabilities,
+    pub plus_verified_capabilities: BadgeCapabilities,
+    pub business_verified_capabilities: BadgeCapabilities,
+    pub verification_period: u64,
+    pub expiry_notice_period: u64,
+    pub refund_grace_period: u64,
+    pub tip_fee_basis_points: u64,
+    pub duplicate_window: u64,
+    pub minimum_age: u64,
+    pub max_scheduled_posts: u64,
+    pub max_schedule_ahead: u64,
+}
+
+#[derive(CandidType)]
+pub struct BadgeCapabilitiesQuery{
+    pub content_limits: ContentLimits,
+    pub capabilities: BadgeCapabilities,
+}
+
+impl Default for Config{
+    fn default() -> Self {
+        Self{
+            profile_limits: ProfileLimits{
+                max_first_name_length: 15,
+                max_middle_name_length: 10,
+                max_last_name_length: 15,
+                max_username_length: 20,
+                max_organization_name_length: 100,
+                max_registration_number_length: 50,
+                max_website_length: 200,
+            },
+            content_limits: ContentLimits{
+                max_topic_length: 50,
+                max_message_length: 700,
+                max_links: 3,
+            },
+            plus_verified_content_limits: None,
+            business_verified_content_limits: None,
+            regular_verified_capabilities: BadgeCapabilities{
+                posts_per_day: 10,
+                max_pinned_posts: 0,
+                edit_window: 0,
+                can_attach_links: false,
+                can_schedule_posts: false,
+                rate_limit: RateLimit{
+                    capacity: 5,
+                    refill_interval: 12 * 1_000_000_000,
+                },
+            },
+            plus_verified_capabilities: BadgeCapabilities{
+                posts_per_day: 50,
+                max_pinned_posts: 3,
+                edit_window: 60 * 60 * 1_000_000_000,
+                can_attach_links: true,
+                can_schedule_posts: true,
+                rate_limit: RateLimit{
+                    capacity: 20,
+                    refill_interval: 3 * 1_000_000_000,
+                },
+            },
+            business_verified_capabilities: BadgeCapabilities{
+                posts_per_day: 200,
+                max_pinned_posts: 5,
+                edit_window: 60 * 60 * 1_000_000_000,
+                can_attach_links: true,
+                can_schedule_posts: true,
+                rate_limit: RateLimit{
+                    capacity: 60,
+                    refill_interval: 1_000_000_000,
+                },
+            },
+            verification_period: 30 * 24 * 60 * 60 * 1_000_000_000,
+            expiry_notice_period: 3 * 24 * 60 * 60 * 1_000_000_000,
+            refund_grace_period: 7 * 24 * 60 * 60 * 1_000_000_000,
+            tip_fee_basis_points: 0,
+            duplicate_window: 60 * 60 * 1_000_000_000,
+            minimum_age: 13,
+            max_scheduled_posts: 25,
+            max_schedule_ahead: 90 * 24 * 60 * 60 * 1_000_000_000,
+        }
+    }
+}
+
+impl Config{
+    fn content_limits_for(&self, verification_badge: &VerificationBadge) -> ContentLimits{
+        let badge_limits = match verification_badge{
+            VerificationBadge::RegularVerified => None,
+            VerificationBadge::PlusVerified => self.plus_verified_content_limits,
+            VerificationBadge::BusinessVerified => self.business_verified_content_limits,
+        };
+        badge_limits.unwrap_or(self.content_limits)
+    }
+
+    fn capabilities_for(&self, verification_badge: &VerificationBadge) -> BadgeCapabilities{
+        match verification_badge{
+            VerificationBadge::RegularVerified => self.regular_verified_capabilities,
+            VerificationBadge::PlusVerified => self.plus_verified_capabilities,
+            VerificationBadge::BusinessVerified => self.business_verified_capabilities,
+        }
+    }
+}
+
+thread_local! {
+    pub static CONFIG: RefCell<Config> = RefCell::default();
+}
+
+pub(crate) fn _query_config() -> Config{
+    CONFIG.with(|state| *state.borrow())
+}
+
+pub(crate) fn _update_config(new_config: Config){
+    CONFIG.with(|state| *state.borrow_mut() = new_config);
+}
+
+pub(crate) fn _profile_limits() -> ProfileLimits{
+    CONFIG.with(|state| state.borrow().profile_limits)
+}
+
+pub(crate) fn _content_limits(verification_badge: &VerificationBadge) -> ContentLimits{
+    CONFIG.with(|state| state.borrow().content_limits_for(verification_badge))
+}
+
+pub(crate) fn _verification_period() -> u64{
+    CONFIG.with(|state| state.borrow().verification_period)
+}
+
+pub(crate) fn _expiry_notice_period() -> u64{
+    CONFIG.with(|state| state.borrow().expiry_notice_period)
+}
+
+pub(crate) fn _refund_grace_period() -> u64{
+    CONFIG.with(|state| state.borrow().refund_grace_period)
+}
+
+pub(crate) fn _duplicate_window() -> u64{
+    CONFIG.with(|state| state.borrow().duplicate_window)
+}
+
+pub(crate) fn _minimum_age() -> u64{
+    CONFIG.with(|state| state.borrow().minimum_age)
+}
+
+pub(crate) fn _max_scheduled_posts() -> u64{
+    CONFIG.with(|state| state.borrow().max_scheduled_posts)
+}
+
+pub(crate) fn _max_schedule_ahead() -> u64{
+    CONFIG.with(|state| state.borrow().max_schedule_ahead)
+}
+
+/// The platform's cut of a tip of `amount`, never more than the tip itself.
+pub(crate) fn _tip_fee(amount: &Nat) -> Nat{
+    let basis_points = CONFIG.with(|state| state.borrow().tip_fee_basis_points).min(10_000);
+    amount.clone() * Nat::from(basis_points) / Nat::from(10_000u64)
+}
+
+pub(crate) fn _badge_capabilities(verification_badge: &VerificationBadge) -> BadgeCapabilities{
+    CONFIG.with(|state| state.borrow().capabilities_for(verification_badge))
+}
+
+#[update]
+#[candid_method(update)]
+pub fn update_config(new_config: Config) -> UpdateStateResponse{
+    let caller = ic_cdk::caller();
+    if !is_this_caller_authority(&caller){
+        UpdateStateResponse::Unauthorized
+    }else{
+        _update_config(new_config);
+        UpdateStateResponse::Success
+    }
+}
+
+#[query]
+#[candid_method(query)]
+pub fn get_config() -> Config{
+    _query_config()
+}
+
+#[query]
+#[candid_method(query)]
+pub fn get_badge_capabilities(verification_badge: VerificationBadge) -> BadgeCapabilitiesQuery{
+    BadgeCapabilitiesQuery{
+        content_limits: _content_limits(&verification_badge),
+        capabilities: _badge_capabilities(&verification_badge),
+    }
+}
\ No newline at end of file
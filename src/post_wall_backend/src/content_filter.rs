@@ -0,0 +1,123 @@
+use std::cell::RefCell;
+
+use candid::{CandidType, candid_method};
+use ic_cdk_macros::*;
+use ic_stable_memory::{SBox, derive::{AsFixedSizeBytes, StableType}, collections::SVec};
+
+use crate::{moderation::can_moderate, response::{UpdateStateResponse, BlockedWordsResponse}};
+
+/// Why a post was rejected as spam or abuse.
+#[derive(CandidType, Clone, PartialEq)]
+pub enum ContentRejection{
+    BlockedWord,
+    TooManyLinks,
+    DuplicateMessage,
+}
+
+/// Words and phrases posts may not contain, stored lowercased.
+#[derive(StableType, AsFixedSizeBytes)]
+pub struct FilterState{
+    pub blocked_words: SVec<SBox<String>>,
+}
+
+impl Default for FilterState{
+    fn default() -> Self {
+        Self{
+            blocked_words: SVec::new(),
+        }
+    }
+}
+
+impl FilterState{
+    fn add_blocked_word(&mut self, word: String) -> bool{
+        if self.blocked_words.iter().any(|blocked_word| **blocked_word == word){
+            return true
+        }
+        match SBox::new(word){
+            Ok(word) => self.blocked_words.push(word).is_ok(),
+            Err(_) => false
+        }
+    }
+
+    fn remove_blocked_word(&mut self, word: &String){
+        if let Some(index) = self.blocked_words.iter().position(|blocked_word| **blocked_word == *word){
+            self.blocked_words.remove(index);
+        }
+    }
+
+    fn blocked_words(&self) -> Vec<String>{
+        self.blocked_words.iter().map(|word| word.clone()).collect()
+    }
+}
+
+thread_local! {
+    pub static FILTER_STATE: RefCell<FilterState> = RefCell::default();
+}
+
+pub(crate) fn _blocked_words() -> Vec<String>{
+    FILTER_STATE.with(|state| state.borrow().blocked_words())
+}
+
+fn normalize_term(term: &str) -> String{
+    term.trim().to_lowercase()
+}
+
+/// Whether `text` contains `term` as a whole word or phrase, ignoring case.
+/// `term` must already be lowercased.
+pub(crate) fn contains_term(text: &str, term: &str) -> bool{
+    if term.is_empty(){
+        return false
+    }
+    let text = text.to_lowercase();
+    text.match_indices(term).any(|(start, _)|{
+        let end = start + term.len();
+        let starts_word = text[..start].chars().next_back().is_none_or(|c| !c.is_alphanumeric());
+        let ends_word = text[end..].chars().next().is_none_or(|c| !c.is_alphanumeric());
+        starts_word && ends_word
+    })
+}
+
+#[update]
+#[candid_method(update)]
+pub fn add_blocked_word(word: String) -> UpdateStateResponse{
+    let caller = ic_cdk::caller();
+    if !can_moderate(&caller){
+        return UpdateStateResponse::Unauthorized
+    }
+    FILTER_STATE.with(|state| state.borrow_mut().add_blocked_word(normalize_term(&word)));
+    UpdateStateResponse::Success
+}
+
+#[update]
+#[candid_method(update)]
+pub fn remove_blocked_word(word: String) -> UpdateStateResponse{
+    let caller = ic_cdk::caller();
+    if !can_moderate(&caller){
+        return UpdateStateResponse::Unauthorized
+    }
+    FILTER_STATE.with(|state| state.borrow_mut().remove_blocked_word(&normalize_term(&word)));
+    UpdateStateResponse::Success
+}
+
+#[query]
+#[candid_method(query)]
+pub fn get_blocked_words() -> BlockedWordsResponse{
+    let caller = ic_cdk::caller();
+    if !can_moderate(&caller){
+        return BlockedWordsResponse::Unauthorized
+    }
+    BlockedWordsResponse::Success(_blocked_words())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn terms_only_match_whole_words() {
+        assert!(contains_term("Buy CHEAP pills now", "cheap pills"));
+        assert!(contains_term("cheap!", "cheap"));
+        assert!(!contains_term("cheapest deals", "cheap"));
+        assert!(!contains_term("anything", ""));
+    }
+}
\ No newline at end of file
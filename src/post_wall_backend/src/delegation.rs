@@ -0,0 +1,204 @@
+use std::cell::RefCell;
+
+use candid::{CandidType, candid_method, Deserialize, Principal};
+use ic_cdk_macros::*;
+use ic_stable_memory::{derive::{AsFixedSizeBytes, StableType}, collections::{SHashMap, SVec}};
+
+use crate::{registration::{get_username, get_address, _suspended_until}, post::{CreatePostData, _create_post_for, _edit_post_for}, rate_limit::_check_rate_limit, response::{DelegationResponse, CreatePostResponse, EditPostResponse}};
+
+/// What a delegate may do for the account that authorized it.
+#[derive(CandidType, AsFixedSizeBytes, StableType, Deserialize, Clone, Copy)]
+pub struct DelegatePermissions{
+    pub can_create_posts: bool,
+    pub can_edit_posts: bool,
+}
+
+/// A principal, such as a bot or a social media manager, allowed to post for
+/// an account until `expires_at`, or until removed when it is `None`.
+#[derive(CandidType, AsFixedSizeBytes, StableType, Clone, Copy)]
+pub struct Delegation{
+    pub delegate: Principal,
+    pub permissions: DelegatePermissions,
+    pub expires_at: Option<u64>,
+}
+
+#[derive(StableType, AsFixedSizeBytes)]
+pub struct DelegationState{
+    pub delegations: SHashMap<Principal, SVec<Delegation>>,
+}
+
+impl Default for DelegationState{
+    fn default() -> Self {
+        Self{
+            delegations: SHashMap::new(),
+        }
+    }
+}
+
+impl DelegationState{
+    /// Authorizes `delegation.delegate` for `author`, replacing an earlier
+    /// delegation to the same principal.
+    fn set_delegation(&mut self, author: Principal, delegation: Delegation) -> bool{
+        if !self.delegations.contains_key(&author) && self.delegations.insert(author, SVec::new()).is_err(){
+            return false
+        }
+        let mut delegations = match self.delegations.get_mut(&author){
+            None => return false,
+            Some(delegations) => delegations
+        };
+        for index in 0..delegations.len(){
+            if let Some(mut existing) = delegations.get_mut(index){
+                if existing.delegate == delegation.delegate{
+                    *existing = delegation;
+                    return true
+                }
+            }
+        }
+        delegations.push(delegation).is_ok()
+    }
+
+    fn remove_delegation(&mut self, author: &Principal, delegate: &Principal){
+        if let Some(mut delegations) = self.delegations.get_mut(author){
+            if let Some(index) = delegations.iter().position(|existing| existing.delegate == *delegate){
+                delegations.remove(index);
+            }
+        }
+    }
+
+    /// The permissions `delegate` holds for `author` at `now`.
+    fn permissions(&self, author: &Principal, delegate: &Principal, now: u64) -> Option<DelegatePermissions>{
+        self.delegations.get(author)?.iter()
+            .find(|existing| existing.delegate == *delegate && existing.expires_at.is_none_or(|expires_at| expires_at > now))
+            .map(|existing| existing.permissions)
+    }
+
+    fn delegations(&self, author: &Principal) -> Vec<Delegation>{
+        match self.delegations.get(author){
+            None => Vec::new(),
+            Some(delegations) => delegations.iter().map(|delegation| *delegation).collect()
+        }
+    }
+}
+
+thread_local! {
+    pub static DELEGATION_STATE: RefCell<DelegationState> = RefCell::default();
+}
+
+pub(crate) fn _remove_delegations_of(author: &Principal){
+    DELEGATION_STATE.with(|state| state.borrow_mut().delegations.remove(author));
+}
+
+/// The account `username` names, if `delegate` may currently act for it with
+/// the permission `allowed` picks.
+fn _author_for(delegate: &Principal, username: &str, allowed: fn(&DelegatePermissions) -> bool) -> Option<Principal>{
+    let author = get_address(username)?;
+    let now = ic_cdk::api::time();
+    let permissions = DELEGATION_STATE.with(|state| state.borrow().permissions(&author, delegate, now))?;
+    if allowed(&permissions){ Some(author) }else{ None }
+}
+
+#[update]
+#[candid_method(update)]
+pub fn add_delegate(delegate: Principal, permissions: DelegatePermissions, expires_at: Option<u64>) -> DelegationResponse{
+    let caller = ic_cdk::caller();
+    if let Err(retry_after_ns) = _check_rate_limit(caller, "add_delegate"){
+        return DelegationResponse::RateLimited{ retry_after_ns }
+    }
+    if get_username(&caller).is_none(){
+        return DelegationResponse::UserNotRegistered
+    }
+    let now = ic_cdk::api::time();
+    if let Some(until) = _suspended_until(&caller, now){
+        return DelegationResponse::Suspended{ until }
+    }
+    if delegate == caller || delegate == Principal::anonymous(){
+        return DelegationResponse::InvalidDelegate
+    }
+    if expires_at.is_some_and(|expires_at| expires_at <= now){
+        return DelegationResponse::AlreadyExpired
+    }
+    let delegation = Delegation{ delegate, permissions, expires_at };
+    if !DELEGATION_STATE.with(|state| state.borrow_mut().set_delegation(caller, delegation)){
+        return DelegationResponse::FailedToAllocateMemory
+    }
+    DelegationResponse::Success
+}
+
+#[update]
+#[candid_method(update)]
+pub fn remove_delegate(delegate: Principal) -> DelegationResponse{
+    let caller = ic_cdk::caller();
+    if get_username(&caller).is_none(){
+        return DelegationResponse::UserNotRegistered
+    }
+    DELEGATION_STATE.with(|state| state.borrow_mut().remove_delegation(&caller, &delegate));
+    DelegationResponse::Success
+}
+
+/// The caller's delegations, expired ones included until removed.
+#[query]
+#[candid_method(query)]
+pub fn get_delegates() -> Vec<Delegation>{
+    let caller = ic_cdk::caller();
+    DELEGATION_STATE.with(|state| state.borrow().delegations(&caller))
+}
+
+/// Publishes a post of `username` for a delegate allowed to create posts.
+/// The post counts against the author's limits, and neither the delegate
+/// nor the author may be suspended or banned.
+#[update]
+#[candid_method(update)]
+pub fn create_post_on_behalf_of(username: String, create_post_data: CreatePostData) -> CreatePostResponse{
+    let caller = ic_cdk::caller();
+    if let Err(retry_after_ns) = _check_rate_limit(caller, "create_post"){
+        return CreatePostResponse::RateLimited{ retry_after_ns }
+    }
+    if let Some(until) = _suspended_until(&caller, ic_cdk::api::time()){
+        return CreatePostResponse::Suspended{ until }
+    }
+    let author = match _author_for(&caller, &username, |permissions| permissions.can_create_posts){
+        None => return CreatePostResponse::NotAuthorized,
+        Some(author) => author
+    };
+    _create_post_for(author, Some(caller), create_post_data)
+}
+
+#[update]
+#[candid_method(update)]
+pub fn edit_post_on_behalf_of(username: String, post_id: u64, create_post_data: CreatePostData) -> EditPostResponse{
+    let caller = ic_cdk::caller();
+    if let Err(retry_after_ns) = _check_rate_limit(caller, "edit_post"){
+        return EditPostResponse::RateLimited{ retry_after_ns }
+    }
+    if let Some(until) = _suspended_until(&caller, ic_cdk::api::time()){
+        return EditPostResponse::Suspended{ until }
+    }
+    let author = match _author_for(&caller, &username, |permissions| permissions.can_edit_posts){
+        None => return EditPostResponse::NotAuthorized,
+        Some(author) => author
+    };
+    _edit_post_for(author, post_id, create_post_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{principal, init_memory};
+
+    const POSTER: DelegatePermissions = DelegatePermissions{ can_create_posts: true, can_edit_posts: false };
+
+    #[test]
+    fn delegations_expire_and_can_be_replaced() {
+        init_memory();
+        let mut state = DelegationState::default();
+        assert!(state.set_delegation(principal(1), Delegation{ delegate: principal(2), permissions: POSTER, expires_at: Some(100) }));
+        assert!(state.permissions(&principal(1), &principal(2), 99).is_some());
+        assert!(state.permissions(&principal(1), &principal(2), 100).is_none());
+        assert!(state.permissions(&principal(3), &principal(2), 0).is_none());
+        assert!(state.set_delegation(principal(1), Delegation{ delegate: principal(2), permissions: POSTER, expires_at: None }));
+        assert_eq!(state.delegations(&principal(1)).len(), 1);
+        assert!(state.permissions(&principal(1), &principal(2), u64::MAX).is_some());
+        state.remove_delegation(&principal(1), &principal(2));
+        assert!(state.permissions(&principal(1), &principal(2), 0).is_none());
+    }
+}
@@ -0,0 +1,91 @@
+use candid::{CandidType, Deserialize, Nat, Principal};
+
+/// ICRC-1 account.
+#[derive(CandidType, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Account{
+    pub owner: Principal,
+    pub subaccount: Option<[u8; 32]>,
+}
+
+impl Account{
+    pub(crate) fn of(owner: Principal) -> Self{
+        Self{ owner, subaccount: None }
+    }
+}
+
+/// Arguments of ICRC-2 `icrc2_transfer_from`.
+#[derive(CandidType, Deserialize, Clone)]
+pub struct TransferFromArgs{
+    pub spender_subaccount: Option<[u8; 32]>,
+    pub from: Account,
+    pub to: Account,
+    pub amount: Nat,
+    pub fee: Option<Nat>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub enum TransferFromError{
+    BadFee{ expected_fee: Nat },
+    BadBurn{ min_burn_amount: Nat },
+    InsufficientFunds{ balance: Nat },
+    InsufficientAllowance{ allowance: Nat },
+    TooOld,
+    CreatedInFuture{ ledger_time: u64 },
+    Duplicate{ duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError{ error_code: Nat, message: String },
+}
+
+/// Arguments of ICRC-1 `icrc1_transfer`.
+#[derive(CandidType, Deserialize, Clone)]
+pub struct TransferArg{
+    pub from_subaccount: Option<[u8; 32]>,
+    pub to: Account,
+    pub amount: Nat,
+    pub fee: Option<Nat>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum TransferError{
+    BadFee{ expected_fee: Nat },
+    BadBurn{ min_burn_amount: Nat },
+    InsufficientFunds{ balance: Nat },
+    TooOld,
+    CreatedInFuture{ ledger_time: u64 },
+    Duplicate{ duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError{ error_code: Nat, message: String },
+}
+
+/// The ICRC ledger calls the canister makes. Implemented by `Icrc2Ledger`
+/// for a configured token and by a stub ledger in the tests.
+pub(crate) trait IcrcLedger{
+    async fn balance_of(&self, account: Account) -> Result<Nat, String>;
+    async fn transfer(&self, args: TransferArg) -> Result<Result<Nat, TransferError>, String>;
+    async fn transfer_from(&self, args: TransferFromArgs) -> Result<Result<Nat, TransferFromError>, String>;
+}
+
+pub(crate) struct Icrc2Ledger{
+    pub canister_id: Principal,
+}
+
+impl IcrcLedger for Icrc2Ledger{
+    async fn balance_of(&self, account: Account) -> Result<Nat, String>{
+        let result: Result<(Nat,), _> = ic_cdk::call(self.canister_id, "icrc1_balance_of", (account,)).await;
+        result.map(|(balance,)| balance).map_err(|(_, message)| message)
+    }
+
+    async fn transfer(&self, args: TransferArg) -> Result<Result<Nat, TransferError>, String>{
+        let result: Result<(Result<Nat, TransferError>,), _> = ic_cdk::call(self.canister_id, "icrc1_transfer", (args,)).await;
+        result.map(|(result,)| result).map_err(|(_, message)| message)
+    }
+
+    async fn transfer_from(&self, args: TransferFromArgs) -> Result<Result<Nat, TransferFromError>, String>{
+        let result: Result<(Result<Nat, TransferFromError>,), _> = ic_cdk::call(self.canister_id, "icrc2_transfer_from", (args,)).await;
+        result.map(|(result,)| result).map_err(|(_, message)| message)
+    }
+}
\ No newline at end of file
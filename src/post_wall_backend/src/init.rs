@@ -1,18 +1,20 @@
-use std::cell::RefCell;
+use std::{cell::RefCell, time::Duration};
 
 use candid::{Principal, candid_method};
 use ic_cdk_macros::*;
-use ic_stable_memory::{derive::{AsFixedSizeBytes, StableType}, stable_memory_init};
-use crate::response::UpdateStateResponse;
+use ic_ledger_types::MAINNET_LEDGER_CANISTER_ID;
+use ic_stable_memory::{derive::{AsFixedSizeBytes, StableType}, stable_memory_init, stable_memory_pre_upgrade, stable_memory_post_upgrade};
+use crate::{response::UpdateStateResponse, verification_status::_expire_verifications, rate_limit::_prune_rate_limits, schedule::_arm_scheduled_posts, upgrade::{_save_state, _restore_state}, registration::_migrate_dates_of_birth};
 
 #[derive(AsFixedSizeBytes, StableType)]
 pub struct InitData{
     pub authority: Principal,
+    pub ledger: Principal,
 }
 
 impl Default for InitData{
     fn default() -> Self {
-        Self { authority: Principal::from_slice(&[]) }
+        Self { authority: Principal::from_slice(&[]), ledger: MAINNET_LEDGER_CANISTER_ID }
     }
 }
 
@@ -20,6 +22,9 @@ thread_local! {
     pub static INIT_DATA: RefCell<InitData> = RefCell::default();
 }
 
+/// How often expired verifications and idle rate limit buckets are swept.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
 pub(crate) fn _query_authority() -> Principal{
     INIT_DATA.with(|state| state.borrow().authority.clone())
 }
@@ -28,6 +33,14 @@ pub(crate) fn _change_authorty(new_authority: Principal){
     INIT_DATA.with(|state| state.borrow_mut().authority = new_authority);
 }
 
+pub(crate) fn _query_ledger() -> Principal{
+    INIT_DATA.with(|state| state.borrow().ledger)
+}
+
+pub(crate) fn _change_ledger(new_ledger: Principal){
+    INIT_DATA.with(|state| state.borrow_mut().ledger = new_ledger);
+}
+
 pub(crate) fn is_this_caller_authority(caller: &Principal) -> bool{
     let authority = _query_authority();
     if *caller != authority{
@@ -45,7 +58,51 @@ pub fn init(){
     INIT_DATA.with(|state|{
         let state = &mut state.borrow_mut();
         state.authority = caller;
-    })
+    });
+    _start_sweep_timer();
+}
+
+#[pre_upgrade]
+fn pre_upgrade(){
+    _save_state();
+    if stable_memory_pre_upgrade().is_err(){
+        ic_cdk::trap("out of stable memory saving the allocator")
+    }
+}
+
+/// Versions before the upgrade hooks never saved the allocator, leaving its
+/// pointer at the start of stable memory unset. Nothing can be restored
+/// from those, so they start over as a fresh install.
+fn _allocator_saved() -> bool{
+    if ic_cdk::api::stable::stable64_size() == 0{
+        return false
+    }
+    let mut pointer = [0; 8];
+    ic_cdk::api::stable::stable64_read(0, &mut pointer);
+    pointer != [0; 8]
+}
+
+/// Restores the state before setting the timers again, as timers do not
+/// survive an upgrade.
+#[post_upgrade]
+fn post_upgrade(){
+    if _allocator_saved(){
+        stable_memory_post_upgrade();
+        _restore_state();
+        _migrate_dates_of_birth();
+    }else{
+        stable_memory_init();
+    }
+    _start_sweep_timer();
+    _arm_scheduled_posts();
+}
+
+fn _start_sweep_timer(){
+    ic_cdk_timers::set_timer_interval(SWEEP_INTERVAL, ||{
+        let now = ic_cdk::api::time();
+        _expire_verifications(now);
+        _prune_rate_limits(now);
+    });
 }
 
 #[update]
@@ -65,4 +122,22 @@ pub fn change_authority(new_authority: Principal) -> UpdateStateResponse{
 #[candid_method(query)]
 pub fn query_authority() -> Principal{
     _query_authority()
+}
+
+#[update]
+#[candid_method(update)]
+pub fn change_ledger(new_ledger: Principal) -> UpdateStateResponse{
+    let caller = ic_cdk::caller();
+    if !is_this_caller_authority(&caller){
+        UpdateStateResponse::Unauthorized
+    }else{
+        _change_ledger(new_ledger);
+        UpdateStateResponse::Success
+    }
+}
+
+#[query]
+#[candid_method(query)]
+pub fn query_ledger() -> Principal{
+    _query_ledger()
 }
\ No newline at end of file
@@ -0,0 +1,112 @@
+use candid::Principal;
+use ic_cdk_macros::*;
+
+use crate::{registration::{get_username, get_verification_badge, _suspended_until}, config::{_profile_limits, _content_limits}, utils::MAX_UTF8_CHAR_LENGTH, verification_status::VerificationBadge};
+
+/// Room for the candid header, field tags and the other arguments around the
+/// strings of a `CreatePostData` or `RegistrationData`.
+const ARGUMENT_OVERHEAD: u64 = 256;
+/// Most scalar values that compose into one under NFC. Limits are counted
+/// after normalization, and no canonical decomposition is longer than four
+/// scalar values, so raw input can hold up to four times as many as the text
+/// that is checked, as with Hangul jamo or decomposed Greek.
+const MAX_NFC_COMPOSITION: u64 = 4;
+/// Most UTF-8 bytes of raw input per scalar value of normalized text.
+const MAX_BYTES_PER_CHAR: u64 = MAX_NFC_COMPOSITION * MAX_UTF8_CHAR_LENGTH;
+
+/// Update methods that a suspended or banned account is turned away from.
+const SUSPENDABLE_METHODS: [&str; 19] = ["create_post", "edit_post", "create_post_as", "edit_post_as", "create_post_on_behalf_of", "edit_post_on_behalf_of", "schedule_post", "add_delegate", "pin_post", "unpin_post", "tip_post", "report_post", "report_user", "update_profile", "set_business_profile", "add_business_member", "delete_my_account", "upgrade_badge", "renew_verification"];
+
+fn largest_registration(names_only: bool) -> u64{
+    let limits = _profile_limits();
+    let names = (limits.max_first_name_length + limits.max_middle_name_length + limits.max_last_name_length) * MAX_BYTES_PER_CHAR;
+    if names_only{
+        names + ARGUMENT_OVERHEAD
+    }else{
+        names + limits.max_username_length + ARGUMENT_OVERHEAD
+    }
+}
+
+fn largest_post(verification_badge: &VerificationBadge) -> u64{
+    let limits = _content_limits(verification_badge);
+    (limits.max_topic_length + limits.max_message_length) * MAX_BYTES_PER_CHAR + ARGUMENT_OVERHEAD
+}
+
+/// Whether an ingress call to `method` is worth executing. Everything
+/// rejected here would be rejected by the method itself, only later and at
+/// the canister's expense.
+fn _accepts(method: &str, caller: &Principal, argument_size: u64, now: u64) -> bool{
+    if *caller == Principal::anonymous(){
+        return false
+    }
+    if SUSPENDABLE_METHODS.contains(&method) && _suspended_until(caller, now).is_some(){
+        return false
+    }
+    match method{
+        "create_post" | "edit_post" | "schedule_post" => match get_verification_badge(caller){
+            None => false,
+            Some(verification_badge) => argument_size <= largest_post(&verification_badge)
+        },
+        // members post under the business account's limits
+        "create_post_as" | "edit_post_as" => get_username(caller).is_some() && argument_size <= largest_post(&VerificationBadge::BusinessVerified),
+        // delegates need no account of their own, so allow any badge's limits
+        "create_post_on_behalf_of" | "edit_post_on_behalf_of" => [VerificationBadge::RegularVerified, VerificationBadge::PlusVerified, VerificationBadge::BusinessVerified].iter()
+            .any(|verification_badge| argument_size <= largest_post(verification_badge)),
+        "register" | "register_as_regular_user" | "register_as_plus_user" | "register_as_business_account" => argument_size <= largest_registration(false),
+        "update_profile" => get_username(caller).is_some() && argument_size <= largest_registration(true),
+        _ => true
+    }
+}
+
+#[inspect_message]
+fn inspect_message(){
+    let caller = ic_cdk::caller();
+    let method = ic_cdk::api::call::method_name();
+    let argument_size = ic_cdk::api::call::arg_data_raw_size() as u64;
+    if _accepts(&method, &caller, argument_size, ic_cdk::api::time()){
+        ic_cdk::api::call::accept_message();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registration::{RegistrationData, Gender};
+    use candid::Encode;
+    use crate::testing::{principal, init_memory};
+
+    #[test]
+    fn anonymous_and_oversize_ingress_is_rejected() {
+        init_memory();
+        assert!(!_accepts("get_config", &Principal::anonymous(), 0, 0));
+        assert!(_accepts("get_config", &principal(2), 0, 0));
+        assert!(_accepts("register_as_plus_user", &principal(2), 200, 0));
+        assert!(!_accepts("register_as_plus_user", &principal(2), 1_000, 0));
+    }
+
+    #[test]
+    fn decomposed_names_at_the_limit_pass_ingress() {
+        init_memory();
+        let limits = _profile_limits();
+        // each syllable arrives as three jamo and normalizes back to one scalar value
+        let name = |length: u64| "\u{1112}\u{1161}\u{11ab}".repeat(length as usize);
+        let registration_data = RegistrationData{
+            first_name: name(limits.max_first_name_length),
+            middle_name: name(limits.max_middle_name_length),
+            last_name: name(limits.max_last_name_length),
+            username: "a".repeat(limits.max_username_length as usize),
+            date_of_birth: 19900101,
+            gender: Gender::RatherNotToSay,
+        };
+        let argument = Encode!(&registration_data, &VerificationBadge::PlusVerified, &None::<Principal>).unwrap();
+        assert!(argument.len() as u64 > (limits.max_first_name_length + limits.max_middle_name_length + limits.max_last_name_length) * MAX_UTF8_CHAR_LENGTH + ARGUMENT_OVERHEAD);
+        assert!(_accepts("register", &principal(2), argument.len() as u64, 0));
+    }
+
+    #[test]
+    fn unregistered_callers_cannot_post() {
+        init_memory();
+        assert!(!_accepts("create_post", &principal(2), 100, 0));
+        assert!(!_accepts("update_profile", &principal(2), 100, 0));
+    }
+}
\ No newline at end of file
@@ -1,7 +1,30 @@
 pub mod init;
+pub mod config;
 pub mod registration;
 pub mod verification_status;
 pub mod post;
 pub mod response;
 pub mod utils;
-pub mod test;
\ No newline at end of file
+pub mod payment;
+pub mod icrc;
+pub mod notification;
+pub mod treasury;
+pub mod refund;
+pub mod receipt;
+pub mod tip;
+pub mod account;
+pub mod moderation;
+pub mod relationship;
+pub mod content_filter;
+pub mod rate_limit;
+pub mod username;
+pub mod business;
+pub mod delegation;
+pub mod schedule;
+pub mod inspect;
+pub mod upgrade;
+pub mod test;
+#[cfg(test)]
+mod mock_ledger;
+#[cfg(test)]
+mod testing;
\ No newline at end of file
@@ -0,0 +1,144 @@
+use std::{cell::RefCell, collections::HashMap, future::Future, task::{Context, Poll, Waker}};
+
+use candid::{Nat, Principal};
+use ic_ledger_types::{AccountIdentifier, Tokens, TransferArgs, TransferError, TransferResult, DEFAULT_FEE, DEFAULT_SUBACCOUNT};
+
+use crate::{payment::Ledger, icrc::{Account, IcrcLedger, TransferArg, TransferError as IcrcTransferError, TransferFromArgs, TransferFromError}};
+
+/// Runs a future that never suspends, which holds for every call into the
+/// mock ledgers.
+pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = std::pin::pin!(future);
+    let mut context = Context::from_waker(Waker::noop());
+    match future.as_mut().poll(&mut context) {
+        Poll::Ready(output) => output,
+        Poll::Pending => panic!("the mock ledger never suspends"),
+    }
+}
+
+/// In-memory stand-in for the ICP ledger, holding balances for the accounts
+/// of a single canister.
+pub(crate) struct MockLedger {
+    canister: Principal,
+    balances: RefCell<HashMap<AccountIdentifier, u64>>,
+    transfers: RefCell<Vec<TransferArgs>>,
+}
+
+impl MockLedger {
+    pub(crate) fn new(canister: Principal) -> Self {
+        Self {
+            canister,
+            balances: RefCell::default(),
+            transfers: RefCell::default(),
+        }
+    }
+
+    pub(crate) fn deposit(&self, account: AccountIdentifier, e8s: u64) {
+        *self.balances.borrow_mut().entry(account).or_default() += e8s;
+    }
+
+    pub(crate) fn balance(&self, account: &AccountIdentifier) -> u64 {
+        self.balances.borrow().get(account).copied().unwrap_or_default()
+    }
+
+    pub(crate) fn transfer_count(&self) -> usize {
+        self.transfers.borrow().len()
+    }
+}
+
+impl Ledger for MockLedger {
+    async fn account_balance(&self, account: AccountIdentifier) -> Result<Tokens, String> {
+        Ok(Tokens::from_e8s(self.balance(&account)))
+    }
+
+    async fn transfer(&self, args: TransferArgs) -> Result<TransferResult, String> {
+        if args.fee != DEFAULT_FEE {
+            return Ok(Err(TransferError::BadFee { expected_fee: DEFAULT_FEE }));
+        }
+        let from = AccountIdentifier::new(&self.canister, &args.from_subaccount.unwrap_or(DEFAULT_SUBACCOUNT));
+        let balance = self.balance(&from);
+        let debit = args.amount.e8s() + args.fee.e8s();
+        if balance < debit {
+            return Ok(Err(TransferError::InsufficientFunds { balance: Tokens::from_e8s(balance) }));
+        }
+        self.balances.borrow_mut().insert(from, balance - debit);
+        self.deposit(args.to, args.amount.e8s());
+        let mut transfers = self.transfers.borrow_mut();
+        transfers.push(args);
+        Ok(Ok(transfers.len() as u64 - 1))
+    }
+}
+
+/// In-memory stand-in for a fee-less ICRC-2 ledger. Only default accounts
+/// are modelled and every allowance is granted to `canister`, which is also
+/// the sender of every `icrc1_transfer`.
+pub(crate) struct MockIcrcLedger {
+    canister: Principal,
+    balances: RefCell<HashMap<Principal, u64>>,
+    allowances: RefCell<HashMap<Principal, u64>>,
+    blocks: RefCell<u64>,
+}
+
+impl MockIcrcLedger {
+    pub(crate) fn new(canister: Principal) -> Self {
+        Self {
+            canister,
+            balances: RefCell::default(),
+            allowances: RefCell::default(),
+            blocks: RefCell::default(),
+        }
+    }
+
+    pub(crate) fn mint(&self, owner: Principal, amount: u64) {
+        *self.balances.borrow_mut().entry(owner).or_default() += amount;
+    }
+
+    pub(crate) fn approve(&self, owner: Principal, amount: u64) {
+        self.allowances.borrow_mut().insert(owner, amount);
+    }
+
+    pub(crate) fn balance(&self, owner: &Principal) -> u64 {
+        self.balances.borrow().get(owner).copied().unwrap_or_default()
+    }
+}
+
+impl MockIcrcLedger {
+    fn next_block(&self) -> Nat {
+        let mut blocks = self.blocks.borrow_mut();
+        *blocks += 1;
+        Nat::from(*blocks - 1)
+    }
+}
+
+impl IcrcLedger for MockIcrcLedger {
+    async fn balance_of(&self, account: Account) -> Result<Nat, String> {
+        Ok(Nat::from(self.balance(&account.owner)))
+    }
+
+    async fn transfer(&self, args: TransferArg) -> Result<Result<Nat, IcrcTransferError>, String> {
+        let amount = u64::try_from(&args.amount.0).map_err(|e| e.to_string())?;
+        let balance = self.balance(&self.canister);
+        if balance < amount {
+            return Ok(Err(IcrcTransferError::InsufficientFunds { balance: Nat::from(balance) }));
+        }
+        self.balances.borrow_mut().insert(self.canister, balance - amount);
+        self.mint(args.to.owner, amount);
+        Ok(Ok(self.next_block()))
+    }
+
+    async fn transfer_from(&self, args: TransferFromArgs) -> Result<Result<Nat, TransferFromError>, String> {
+        let amount = u64::try_from(&args.amount.0).map_err(|e| e.to_string())?;
+        let allowance = self.allowances.borrow().get(&args.from.owner).copied().unwrap_or_default();
+        if allowance < amount {
+            return Ok(Err(TransferFromError::InsufficientAllowance { allowance: Nat::from(allowance) }));
+        }
+        let balance = self.balance(&args.from.owner);
+        if balance < amount {
+            return Ok(Err(TransferFromError::InsufficientFunds { balance: Nat::from(balance) }));
+        }
+        self.allowances.borrow_mut().insert(args.from.owner, allowance - amount);
+        self.balances.borrow_mut().insert(args.from.owner, balance - amount);
+        self.mint(args.to.owner, amount);
+        Ok(Ok(self.next_block()))
+    }
+}
\ No newline at end of file
@@ -0,0 +1,357 @@
+use std::cell::RefCell;
+
+use candid::{CandidType, candid_method, Deserialize, Principal};
+use ic_cdk_macros::*;
+use ic_stable_memory::{derive::{AsFixedSizeBytes, StableType}, collections::SVec};
+
+use crate::{init::is_this_caller_authority, registration::{AccountStatus, get_username, get_address, _add_strike, _set_account_status, _suspended_until}, post::{_post_author, _hide_post, _delete_post}, rate_limit::_check_rate_limit, notification::{_notify, NotificationKind}, response::{UpdateStateResponse, ReportResponse, ReportsResponse, ResolveReportResponse, SuspendUserResponse}};
+
+#[derive(CandidType, AsFixedSizeBytes, StableType, Deserialize, Clone, PartialEq)]
+pub enum ReportReason{
+    Spam,
+    Harassment,
+    HateSpeech,
+    Violence,
+    Impersonation,
+    Misinformation,
+    Other,
+}
+
+#[derive(CandidType, AsFixedSizeBytes, StableType, Clone, PartialEq)]
+pub enum ReportTarget{
+    Post{ post_id: u64 },
+    User{ address: Principal },
+}
+
+/// What a moderator did about a report. Every action but `Dismiss` adds a
+/// strike to the reported user, the author for a reported post.
+#[derive(CandidType, AsFixedSizeBytes, StableType, Deserialize, Clone, PartialEq)]
+pub enum ModerationAction{
+    Dismiss,
+    HidePost,
+    DeletePost,
+    Warn,
+    Suspend{ until: u64 },
+}
+
+#[derive(CandidType, AsFixedSizeBytes, StableType, Clone)]
+pub struct Resolution{
+    pub action: ModerationAction,
+    pub resolved_by: Principal,
+    pub resolved_at: u64,
+}
+
+#[derive(CandidType, AsFixedSizeBytes, StableType, Clone)]
+pub struct Report{
+    pub id: u64,
+    pub reporter: Principal,
+    pub target: ReportTarget,
+    pub reason: ReportReason,
+    pub reported_at: u64,
+    pub resolution: Option<Resolution>,
+}
+
+#[derive(StableType, AsFixedSizeBytes)]
+pub struct ModerationState{
+    pub moderators: SVec<Principal>,
+    pub reports: SVec<Report>,
+}
+
+impl Default for ModerationState{
+    fn default() -> Self {
+        Self{
+            moderators: SVec::new(),
+            reports: SVec::new(),
+        }
+    }
+}
+
+impl ModerationState{
+    fn is_moderator(&self, address: &Principal) -> bool{
+        self.moderators.iter().any(|moderator| *moderator == *address)
+    }
+
+    fn add_moderator(&mut self, address: Principal) -> bool{
+        if self.is_moderator(&address){
+            return true
+        }
+        self.moderators.push(address).is_ok()
+    }
+
+    fn remove_moderator(&mut self, address: &Principal){
+        if let Some(index) = self.moderators.iter().position(|moderator| *moderator == *address){
+            self.moderators.remove(index);
+        }
+    }
+
+    fn file_report(&mut self, reporter: Principal, target: ReportTarget, reason: ReportReason, now: u64) -> ReportResponse{
+        let already_reported = self.reports.iter()
+            .any(|report| report.reporter == reporter && report.target == target && report.resolution.is_none());
+        if already_reported{
+            return ReportResponse::AlreadyReported
+        }
+        let report_id = self.reports.len() as u64;
+        let report = Report{
+            id: report_id,
+            reporter,
+            target,
+            reason,
+            reported_at: now,
+            resolution: None,
+        };
+        match self.reports.push(report){
+            Ok(_) => ReportResponse::Success{ report_id },
+            Err(_) => ReportResponse::FailedToAllocateMemory
+        }
+    }
+
+    fn reports(&self, open_only: bool, start: usize, limit: usize) -> Vec<Report>{
+        self.reports.iter()
+            .filter(|report| !open_only || report.resolution.is_none())
+            .skip(start)
+            .take(limit)
+            .map(|report| report.clone())
+            .collect()
+    }
+
+    fn open_report(&self, report_id: u64) -> Result<Report, ResolveReportResponse>{
+        match self.reports.get(report_id as usize){
+            None => Err(ResolveReportResponse::ReportNotFound),
+            Some(report) if report.resolution.is_some() => Err(ResolveReportResponse::AlreadyResolved),
+            Some(report) => Ok(report.clone())
+        }
+    }
+
+    fn resolve(&mut self, report_id: u64, resolution: Resolution){
+        if let Some(mut report) = self.reports.get_mut(report_id as usize){
+            report.resolution = Some(resolution);
+        }
+    }
+}
+
+thread_local! {
+    pub static MODERATION_STATE: RefCell<ModerationState> = RefCell::default();
+}
+
+pub(crate) fn can_moderate(caller: &Principal) -> bool{
+    is_this_caller_authority(caller) || MODERATION_STATE.with(|state| state.borrow().is_moderator(caller))
+}
+
+fn _file_report(reporter: Principal, target: ReportTarget, reason: ReportReason) -> ReportResponse{
+    let now = ic_cdk::api::time();
+    MODERATION_STATE.with(|state| state.borrow_mut().file_report(reporter, target, reason, now))
+}
+
+#[update]
+#[candid_method(update)]
+pub fn report_post(post_id: u64, reason: ReportReason) -> ReportResponse{
+    let caller = ic_cdk::caller();
+    if let Err(retry_after_ns) = _check_rate_limit(caller, "report_post"){
+        return ReportResponse::RateLimited{ retry_after_ns }
+    }
+    if get_username(&caller).is_none(){
+        return ReportResponse::UserNotRegistered
+    }
+    if let Some(until) = _suspended_until(&caller, ic_cdk::api::time()){
+        return ReportResponse::Suspended{ until }
+    }
+    let author = match _post_author(post_id).and_then(|username| get_address(&username)){
+        None => return ReportResponse::PostNotFound,
+        Some(author) => author
+    };
+    if author == caller{
+        return ReportResponse::CannotReportYourself
+    }
+    _file_report(caller, ReportTarget::Post{ post_id }, reason)
+}
+
+#[update]
+#[candid_method(update)]
+pub fn report_user(username: String, reason: ReportReason) -> ReportResponse{
+    let caller = ic_cdk::caller();
+    if let Err(retry_after_ns) = _check_rate_limit(caller, "report_user"){
+        return ReportResponse::RateLimited{ retry_after_ns }
+    }
+    if get_username(&caller).is_none(){
+        return ReportResponse::UserNotRegistered
+    }
+    if let Some(until) = _suspended_until(&caller, ic_cdk::api::time()){
+        return ReportResponse::Suspended{ until }
+    }
+    let address = match get_address(&username){
+        None => return ReportResponse::UserNotFound,
+        Some(address) => address
+    };
+    if address == caller{
+        return ReportResponse::CannotReportYourself
+    }
+    _file_report(caller, ReportTarget::User{ address }, reason)
+}
+
+#[query]
+#[candid_method(query)]
+pub fn list_reports(open_only: bool, start: u64, limit: u64) -> ReportsResponse{
+    let caller = ic_cdk::caller();
+    if !can_moderate(&caller){
+        return ReportsResponse::Unauthorized
+    }
+    ReportsResponse::Success(MODERATION_STATE.with(|state| state.borrow().reports(open_only, start as usize, limit as usize)))
+}
+
+/// Applies `action` to the target of an open report, records a strike
+/// against the reported user and notifies them.
+#[update]
+#[candid_method(update)]
+pub fn resolve_report(report_id: u64, action: ModerationAction) -> ResolveReportResponse{
+    let caller = ic_cdk::caller();
+    if !can_moderate(&caller){
+        return ResolveReportResponse::Unauthorized
+    }
+    let report = match MODERATION_STATE.with(|state| state.borrow().open_report(report_id)){
+        Err(e) => return e,
+        Ok(report) => report
+    };
+    let offender = match report.target{
+        ReportTarget::Post{ post_id } => _post_author(post_id).and_then(|username| get_address(&username)),
+        ReportTarget::User{ address } => get_username(&address).map(|_| address),
+    };
+    let offender = match (&action, offender){
+        (ModerationAction::Dismiss, _) => None,
+        (_, None) => return ResolveReportResponse::TargetNotFound,
+        (_, Some(offender)) => Some(offender)
+    };
+    let applied = match (&action, &report.target){
+        (ModerationAction::Dismiss, _) | (ModerationAction::Warn, _) => true,
+        (ModerationAction::HidePost, ReportTarget::Post{ post_id }) => _hide_post(*post_id),
+        (ModerationAction::DeletePost, ReportTarget::Post{ post_id }) => _delete_post(*post_id),
+        (ModerationAction::HidePost, ReportTarget::User{ .. }) | (ModerationAction::DeletePost, ReportTarget::User{ .. }) => return ResolveReportResponse::ActionNotApplicable,
+        (ModerationAction::Suspend{ until }, _) => offender.is_some_and(|offender| _set_account_status(&offender, AccountStatus::Suspended{ until: *until })),
+    };
+    if !applied{
+        return ResolveReportResponse::TargetNotFound
+    }
+    if let Some(offender) = offender{
+        _add_strike(&offender);
+        _notify(offender, NotificationKind::ModeratorAction{ report_id, action: action.clone() });
+    }
+    let resolution = Resolution{
+        action,
+        resolved_by: caller,
+        resolved_at: ic_cdk::api::time(),
+    };
+    MODERATION_STATE.with(|state| state.borrow_mut().resolve(report_id, resolution));
+    ResolveReportResponse::Success
+}
+
+fn _change_account_status(caller: &Principal, username: &str, status: AccountStatus, notification: NotificationKind) -> SuspendUserResponse{
+    if !can_moderate(caller){
+        return SuspendUserResponse::Unauthorized
+    }
+    let address = match get_address(username){
+        None => return SuspendUserResponse::UserNotFound,
+        Some(address) => address
+    };
+    if !_set_account_status(&address, status){
+        return SuspendUserResponse::UserNotFound
+    }
+    _notify(address, notification);
+    SuspendUserResponse::Success
+}
+
+/// Stops `username` from writing until `until`, in nanoseconds since the
+/// epoch.
+#[update]
+#[candid_method(update)]
+pub fn suspend_user(username: String, until: u64, reason: ReportReason) -> SuspendUserResponse{
+    let caller = ic_cdk::caller();
+    _change_account_status(&caller, &username, AccountStatus::Suspended{ until }, NotificationKind::AccountSuspended{ until: Some(until), reason })
+}
+
+#[update]
+#[candid_method(update)]
+pub fn ban_user(username: String, reason: ReportReason) -> SuspendUserResponse{
+    let caller = ic_cdk::caller();
+    _change_account_status(&caller, &username, AccountStatus::Banned, NotificationKind::AccountSuspended{ until: None, reason })
+}
+
+/// Lifts a ban or a suspension.
+#[update]
+#[candid_method(update)]
+pub fn unban_user(username: String) -> SuspendUserResponse{
+    let caller = ic_cdk::caller();
+    _change_account_status(&caller, &username, AccountStatus::Active, NotificationKind::AccountReinstated)
+}
+
+#[update]
+#[candid_method(update)]
+pub fn add_moderator(moderator: Principal) -> UpdateStateResponse{
+    let caller = ic_cdk::caller();
+    if !is_this_caller_authority(&caller){
+        return UpdateStateResponse::Unauthorized
+    }
+    MODERATION_STATE.with(|state| state.borrow_mut().add_moderator(moderator));
+    UpdateStateResponse::Success
+}
+
+#[update]
+#[candid_method(update)]
+pub fn remove_moderator(moderator: Principal) -> UpdateStateResponse{
+    let caller = ic_cdk::caller();
+    if !is_this_caller_authority(&caller){
+        return UpdateStateResponse::Unauthorized
+    }
+    MODERATION_STATE.with(|state| state.borrow_mut().remove_moderator(&moderator));
+    UpdateStateResponse::Success
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::init::_change_authorty;
+    use crate::testing::{principal, init_memory};
+
+    fn resolution() -> Resolution {
+        Resolution{
+            action: ModerationAction::Warn,
+            resolved_by: principal(1),
+            resolved_at: 0,
+        }
+    }
+
+    #[test]
+    fn a_reporter_has_one_open_report_per_target() {
+        init_memory();
+        let mut state = ModerationState::default();
+        let target = ReportTarget::Post{ post_id: 7 };
+        assert!(matches!(state.file_report(principal(2), target.clone(), ReportReason::Spam, 0), ReportResponse::Success{ report_id: 0 }));
+        assert!(matches!(state.file_report(principal(2), target.clone(), ReportReason::Other, 0), ReportResponse::AlreadyReported));
+        assert!(matches!(state.file_report(principal(3), target.clone(), ReportReason::Spam, 0), ReportResponse::Success{ report_id: 1 }));
+        state.resolve(0, resolution());
+        assert!(matches!(state.file_report(principal(2), target, ReportReason::Spam, 0), ReportResponse::Success{ report_id: 2 }));
+    }
+
+    #[test]
+    fn resolved_reports_leave_the_open_queue() {
+        init_memory();
+        let mut state = ModerationState::default();
+        state.file_report(principal(2), ReportTarget::User{ address: principal(4) }, ReportReason::Harassment, 0);
+        state.file_report(principal(3), ReportTarget::User{ address: principal(4) }, ReportReason::Harassment, 0);
+        state.resolve(0, resolution());
+        assert_eq!(state.reports(true, 0, 10).iter().map(|report| report.id).collect::<Vec<_>>(), vec![1]);
+        assert_eq!(state.reports(false, 0, 10).len(), 2);
+        assert!(matches!(state.open_report(0), Err(ResolveReportResponse::AlreadyResolved)));
+        assert!(matches!(state.open_report(5), Err(ResolveReportResponse::ReportNotFound)));
+    }
+
+    #[test]
+    fn moderators_can_moderate_until_removed() {
+        init_memory();
+        _change_authorty(principal(1));
+        assert!(can_moderate(&principal(1)));
+        assert!(!can_moderate(&principal(3)));
+        MODERATION_STATE.with(|state| state.borrow_mut().add_moderator(principal(3)));
+        assert!(can_moderate(&principal(3)));
+        MODERATION_STATE.with(|state| state.borrow_mut().remove_moderator(&principal(3)));
+        assert!(!can_moderate(&principal(3)));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,129 @@
+use std::cell::RefCell;
+
+use candid::{CandidType, candid_method, Nat, Principal};
+use ic_cdk_macros::*;
+use ic_stable_memory::{derive::{AsFixedSizeBytes, StableType}, collections::{SHashMap, SVec}};
+
+use crate::moderation::{ModerationAction, ReportReason};
+
+#[derive(CandidType, AsFixedSizeBytes, StableType, Clone)]
+pub enum NotificationKind{
+    VerificationExpiringSoon{ expires_at: u64 },
+    VerificationExpired,
+    PostTipped{ post_id: u64, amount: Nat, token: Option<Principal> },
+    ModeratorAction{ report_id: u64, action: ModerationAction },
+    AccountSuspended{ until: Option<u64>, reason: ReportReason },
+    AccountReinstated,
+    ScheduledPostFailed{ scheduled_post_id: u64 },
+}
+
+#[derive(CandidType, AsFixedSizeBytes, StableType, Clone)]
+pub struct Notification{
+    pub kind: NotificationKind,
+    pub created_at: u64,
+}
+
+#[derive(CandidType)]
+pub struct NotificationPage{
+    pub notifications: Vec<Notification>,
+    pub next_cursor: Option<u64>,
+}
+
+#[derive(StableType, AsFixedSizeBytes)]
+pub struct NotificationState{
+    pub notifications: SHashMap<Principal, SVec<Notification>>,
+}
+
+impl Default for NotificationState{
+    fn default() -> Self {
+        Self{
+            notifications: SHashMap::new(),
+        }
+    }
+}
+
+impl NotificationState{
+    pub const MAX_PAGE_SIZE: usize = 100;
+    /// Notifications kept per inbox. The oldest one is dropped to make room
+    /// for a new one.
+    pub const MAX_INBOX_SIZE: usize = 500;
+
+    pub(crate) fn notify(&mut self, address: Principal, notification: Notification) -> bool{
+        if let Some(mut inbox) = self.notifications.get_mut(&address){
+            if inbox.len() >= Self::MAX_INBOX_SIZE{
+                inbox.remove(0);
+            }
+            return inbox.push(notification).is_ok()
+        }
+        let mut inbox = SVec::new();
+        if inbox.push(notification).is_err(){
+            return false
+        }
+        self.notifications.insert(address, inbox).is_ok()
+    }
+
+    fn page_of(&self, address: &Principal, cursor: u64, limit: usize) -> NotificationPage{
+        let inbox = match self.notifications.get(address){
+            None => return NotificationPage{ notifications: Vec::new(), next_cursor: None },
+            Some(inbox) => inbox
+        };
+        let limit = limit.min(Self::MAX_PAGE_SIZE);
+        let notifications: Vec<Notification> = inbox.iter().skip(cursor as usize).take(limit).map(|notification| notification.clone()).collect();
+        let next = cursor + notifications.len() as u64;
+        let next_cursor = if (next as usize) < inbox.len(){ Some(next) }else{ None };
+        NotificationPage{ notifications, next_cursor }
+    }
+}
+
+thread_local! {
+    pub static NOTIFICATION_STATE: RefCell<NotificationState> = RefCell::default();
+}
+
+pub(crate) fn _notify(address: Principal, kind: NotificationKind) -> bool{
+    let notification = Notification{
+        kind,
+        created_at: ic_cdk::api::time(),
+    };
+    NOTIFICATION_STATE.with(|state| state.borrow_mut().notify(address, notification))
+}
+
+pub(crate) fn _clear_notifications(address: &Principal){
+    NOTIFICATION_STATE.with(|state| state.borrow_mut().notifications.remove(address));
+}
+
+/// The caller's notifications from `cursor` on, oldest first, at most
+/// `NotificationState::MAX_PAGE_SIZE` of them.
+#[query]
+#[candid_method(query)]
+pub fn get_my_notifications(cursor: Option<u64>, limit: u64) -> NotificationPage{
+    let caller = ic_cdk::caller();
+    NOTIFICATION_STATE.with(|state| state.borrow().page_of(&caller, cursor.unwrap_or_default(), limit as usize))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{principal, init_memory};
+
+    fn notification(created_at: u64) -> Notification {
+        Notification{ kind: NotificationKind::VerificationExpired, created_at }
+    }
+
+    #[test]
+    fn notifications_are_paged_and_capped_per_inbox() {
+        init_memory();
+        let mut state = NotificationState::default();
+        for created_at in 0..NotificationState::MAX_INBOX_SIZE as u64 + 2 {
+            assert!(state.notify(principal(2), notification(created_at)));
+        }
+        let page = state.page_of(&principal(2), 0, 1_000);
+        assert_eq!(page.notifications.len(), NotificationState::MAX_PAGE_SIZE);
+        // the two oldest made room for the newest
+        assert_eq!(page.notifications[0].created_at, 2);
+        assert_eq!(page.next_cursor, Some(NotificationState::MAX_PAGE_SIZE as u64));
+        let last = state.page_of(&principal(2), NotificationState::MAX_INBOX_SIZE as u64 - 1, 10);
+        assert_eq!(last.notifications.len(), 1);
+        assert_eq!(last.next_cursor, None);
+        assert!(state.page_of(&principal(3), 0, 10).notifications.is_empty());
+    }
+}
\ No newline at end of file
@@ -0,0 +1,224 @@
+use std::{cell::RefCell, collections::BTreeSet};
+
+use candid::{Nat, Principal};
+use ic_ledger_types::{AccountIdentifier, AccountBalanceArgs, BlockIndex, Memo, Tokens, TransferArgs, TransferError, TransferResult, DEFAULT_FEE, DEFAULT_SUBACCOUNT, account_balance, transfer};
+
+use crate::{init::_query_ledger, utils::subaccount_generator, icrc::{Account, IcrcLedger, Icrc2Ledger, TransferArg, TransferFromArgs, TransferFromError}};
+
+pub(crate) enum PaymentError{
+    AmountTooLarge,
+    InsufficientDeposit,
+    PaymentInProgress,
+    LedgerError,
+}
+
+/// The ledger calls the canister makes. Implemented by `IcpLedger` for the
+/// configured ledger canister and by a mock ledger in the tests.
+pub(crate) trait Ledger{
+    async fn account_balance(&self, account: AccountIdentifier) -> Result<Tokens, String>;
+    async fn transfer(&self, args: TransferArgs) -> Result<TransferResult, String>;
+}
+
+pub(crate) struct IcpLedger{
+    pub canister_id: Principal,
+}
+
+impl IcpLedger{
+    pub(crate) fn configured() -> Self{
+        Self{ canister_id: _query_ledger() }
+    }
+}
+
+impl Ledger for IcpLedger{
+    async fn account_balance(&self, account: AccountIdentifier) -> Result<Tokens, String>{
+        account_balance(self.canister_id, AccountBalanceArgs{ account }).await.map_err(|(_, message)| message)
+    }
+
+    async fn transfer(&self, args: TransferArgs) -> Result<TransferResult, String>{
+        transfer(self.canister_id, args).await.map_err(|(_, message)| message)
+    }
+}
+
+thread_local! {
+    static PAYMENTS_IN_PROGRESS: RefCell<BTreeSet<Principal>> = RefCell::default();
+}
+
+/// Reserves the payer's deposit subaccount so only one ledger operation
+/// touches it at a time. Returns `false` when it is already reserved.
+pub(crate) fn lock_deposit(payer: Principal) -> bool{
+    PAYMENTS_IN_PROGRESS.with(|state| state.borrow_mut().insert(payer))
+}
+
+pub(crate) fn unlock_deposit(payer: &Principal){
+    PAYMENTS_IN_PROGRESS.with(|state| state.borrow_mut().remove(payer));
+}
+
+pub(crate) fn nat_to_e8s(amount: &Nat) -> Option<u64>{
+    u64::try_from(&amount.0).ok()
+}
+
+/// Collects `amount` from the payer into the canister's default account.
+/// Without a `payment_token` the amount is in ICP e8s taken from the payer's
+/// deposit subaccount; with one it is taken from the payer's account on that
+/// ICRC-2 ledger through an approval. A zero amount is not sent to any ledger
+/// and yields `None`.
+pub(crate) async fn collect_payment(payer: Principal, amount: &Nat, payment_token: Option<Principal>) -> Result<Option<BlockIndex>, PaymentError>{
+    if nat_to_e8s(amount) == Some(0){
+        return Ok(None)
+    }
+    if !lock_deposit(payer){
+        return Err(PaymentError::PaymentInProgress)
+    }
+    // only ICP amounts have to fit in e8s; token amounts go to the ledger as they are
+    let result = match (payment_token, nat_to_e8s(amount)){
+        (None, None) => Err(PaymentError::AmountTooLarge),
+        (None, Some(e8s)) => _collect_payment(&IcpLedger::configured(), ic_cdk::id(), payer, e8s).await,
+        (Some(canister_id), _) => _collect_icrc_payment(&Icrc2Ledger{ canister_id }, ic_cdk::id(), payer, amount.clone(), ic_cdk::api::time()).await,
+    };
+    unlock_deposit(&payer);
+    result.map(Some)
+}
+
+/// Pays back `amount` that `collect_payment` took from the payer: ICP goes
+/// back to the payer's deposit subaccount and tokens to the payer's account.
+/// The treasury bears the ledger fee.
+pub(crate) async fn refund_payment(payer: Principal, amount: &Nat, payment_token: Option<Principal>) -> Result<BlockIndex, PaymentError>{
+    match (payment_token, nat_to_e8s(amount)){
+        (None, None) => Err(PaymentError::AmountTooLarge),
+        (None, Some(e8s)) => _refund_payment(&IcpLedger::configured(), ic_cdk::id(), payer, e8s).await,
+        (Some(canister_id), _) => _refund_icrc_payment(&Icrc2Ledger{ canister_id }, payer, amount.clone(), ic_cdk::api::time()).await,
+    }
+}
+
+pub(crate) async fn _refund_payment<L: Ledger>(ledger: &L, canister: Principal, payer: Principal, amount: u64) -> Result<BlockIndex, PaymentError>{
+    let transfer_args = TransferArgs{
+        memo: Memo(0),
+        amount: Tokens::from_e8s(amount),
+        fee: DEFAULT_FEE,
+        from_subaccount: None,
+        to: AccountIdentifier::new(&canister, &subaccount_generator(&payer)),
+        created_at_time: None,
+    };
+    match ledger.transfer(transfer_args).await{
+        Ok(Ok(block_index)) => Ok(block_index),
+        _ => Err(PaymentError::LedgerError)
+    }
+}
+
+pub(crate) async fn _refund_icrc_payment<L: IcrcLedger>(ledger: &L, payer: Principal, amount: Nat, now: u64) -> Result<BlockIndex, PaymentError>{
+    let transfer_arg = TransferArg{
+        from_subaccount: None,
+        to: Account::of(payer),
+        amount,
+        fee: None,
+        memo: None,
+        created_at_time: Some(now),
+    };
+    match ledger.transfer(transfer_arg).await{
+        Ok(Ok(block_index)) => nat_to_e8s(&block_index).ok_or(PaymentError::LedgerError),
+        _ => Err(PaymentError::LedgerError)
+    }
+}
+
+pub(crate) async fn _collect_payment<L: Ledger>(ledger: &L, canister: Principal, payer: Principal, amount: u64) -> Result<BlockIndex, PaymentError>{
+    let subaccount = subaccount_generator(&payer);
+    let balance = match ledger.account_balance(AccountIdentifier::new(&canister, &subaccount)).await{
+        Ok(balance) => balance,
+        Err(_) => return Err(PaymentError::LedgerError)
+    };
+    if balance.e8s() < amount.saturating_add(DEFAULT_FEE.e8s()){
+        return Err(PaymentError::InsufficientDeposit)
+    }
+    _send_from_deposit(ledger, payer, AccountIdentifier::new(&canister, &DEFAULT_SUBACCOUNT), amount).await
+}
+
+/// Sends `amount` e8s from the payer's deposit subaccount to `to`.
+pub(crate) async fn _send_from_deposit<L: Ledger>(ledger: &L, payer: Principal, to: AccountIdentifier, amount: u64) -> Result<BlockIndex, PaymentError>{
+    let transfer_args = TransferArgs{
+        memo: Memo(0),
+        amount: Tokens::from_e8s(amount),
+        fee: DEFAULT_FEE,
+        from_subaccount: Some(subaccount_generator(&payer)),
+        to,
+        created_at_time: None,
+    };
+    match ledger.transfer(transfer_args).await{
+        Ok(Ok(block_index)) => Ok(block_index),
+        Ok(Err(TransferError::InsufficientFunds{ .. })) => Err(PaymentError::InsufficientDeposit),
+        _ => Err(PaymentError::LedgerError)
+    }
+}
+
+pub(crate) async fn _collect_icrc_payment<L: IcrcLedger>(ledger: &L, canister: Principal, payer: Principal, amount: Nat, now: u64) -> Result<BlockIndex, PaymentError>{
+    _send_with_approval(ledger, payer, Account::of(canister), amount, now).await
+}
+
+/// Sends `amount` from the payer's account to `to` using the allowance the
+/// payer granted this canister.
+pub(crate) async fn _send_with_approval<L: IcrcLedger>(ledger: &L, payer: Principal, to: Account, amount: Nat, now: u64) -> Result<BlockIndex, PaymentError>{
+    let transfer_from_args = TransferFromArgs{
+        spender_subaccount: None,
+        from: Account::of(payer),
+        to,
+        amount,
+        fee: None,
+        memo: None,
+        created_at_time: Some(now),
+    };
+    match ledger.transfer_from(transfer_from_args).await{
+        Ok(Ok(block_index)) => nat_to_e8s(&block_index).ok_or(PaymentError::LedgerError),
+        Ok(Err(TransferFromError::InsufficientAllowance{ .. })) | Ok(Err(TransferFromError::InsufficientFunds{ .. })) => Err(PaymentError::InsufficientDeposit),
+        _ => Err(PaymentError::LedgerError)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::principal;
+    use crate::mock_ledger::{block_on, MockIcrcLedger, MockLedger};
+
+    #[test]
+    fn icp_payments_move_the_deposit_into_the_treasury() {
+        let ledger = MockLedger::new(principal(100));
+        let deposit = AccountIdentifier::new(&principal(100), &subaccount_generator(&principal(2)));
+        ledger.deposit(deposit, 500_000);
+        assert!(block_on(_collect_payment(&ledger, principal(100), principal(2), 300_000)).is_ok());
+        assert_eq!(ledger.balance(&AccountIdentifier::new(&principal(100), &DEFAULT_SUBACCOUNT)), 300_000);
+        assert!(matches!(block_on(_collect_payment(&ledger, principal(100), principal(2), 300_000)), Err(PaymentError::InsufficientDeposit)));
+    }
+
+    #[test]
+    fn icrc_payments_use_the_approved_allowance() {
+        let ledger = MockIcrcLedger::new(principal(100));
+        ledger.mint(principal(2), 1_000);
+        ledger.approve(principal(2), 600);
+        let block_index = block_on(_collect_icrc_payment(&ledger, principal(100), principal(2), Nat::from(500), 0));
+        assert!(block_index.is_ok());
+        assert_eq!(ledger.balance(&principal(100)), 500);
+        assert_eq!(ledger.balance(&principal(2)), 500);
+        let result = block_on(_collect_icrc_payment(&ledger, principal(100), principal(2), Nat::from(500), 0));
+        assert!(matches!(result, Err(PaymentError::InsufficientDeposit)));
+    }
+
+    #[test]
+    fn icrc_payments_without_an_approval_are_rejected() {
+        let ledger = MockIcrcLedger::new(principal(100));
+        ledger.mint(principal(2), 1_000);
+        let result = block_on(_collect_icrc_payment(&ledger, principal(100), principal(2), Nat::from(500), 0));
+        assert!(matches!(result, Err(PaymentError::InsufficientDeposit)));
+        assert_eq!(ledger.balance(&principal(2)), 1_000);
+    }
+
+    #[test]
+    fn refunds_return_the_payment_to_where_it_came_from() {
+        let ledger = MockLedger::new(principal(100));
+        ledger.deposit(AccountIdentifier::new(&principal(100), &DEFAULT_SUBACCOUNT), 500_000);
+        assert!(block_on(_refund_payment(&ledger, principal(100), principal(2), 300_000)).is_ok());
+        assert_eq!(ledger.balance(&AccountIdentifier::new(&principal(100), &subaccount_generator(&principal(2)))), 300_000);
+        let token_ledger = MockIcrcLedger::new(principal(100));
+        token_ledger.mint(principal(100), 500);
+        assert!(block_on(_refund_icrc_payment(&token_ledger, principal(2), Nat::from(500), 0)).is_ok());
+        assert_eq!(token_ledger.balance(&principal(2)), 500);
+    }
+}
@@ -1,8 +1,8 @@
 use std::cell::RefCell;
 
-use candid::{CandidType, candid_method, Deserialize};
+use candid::{CandidType, candid_method, Deserialize, Nat, Principal};
 use ic_stable_memory::{SBox, derive::{AsFixedSizeBytes, StableType}, collections::{SHashMap, SVec}};
-use crate::{response::CreatePostResponse, registration::get_username};
+use crate::{response::{CreatePostResponse, EditPostResponse, PinPostResponse}, registration::{get_username, get_verification_badge, get_address, _suspended_until}, relationship::{_has_blocked, _has_muted}, rate_limit::_check_rate_limit, config::{ContentLimits, BadgeCapabilities, _content_limits, _badge_capabilities, _duplicate_window}, content_filter::{ContentRejection, contains_term, _blocked_words}, verification_status::VerificationBadge, utils::{text_length, normalize_text}};
 use ic_cdk_macros::*;
 
 #[derive(CandidType, AsFixedSizeBytes, StableType, Default, Clone, Copy)]
@@ -12,19 +12,48 @@ pub struct ReactionCount{
     pub dislike: u128,
 }
 
+/// Everything tipped to a post in one token, `None` being ICP.
+#[derive(CandidType, AsFixedSizeBytes, StableType, Clone)]
+pub struct TipTotal{
+    pub token: Option<Principal>,
+    pub amount: Nat,
+}
+
 #[derive(CandidType, AsFixedSizeBytes, StableType)]
 pub struct Post{
+    pub id: u64,
     pub topic: SBox<String>,
     pub message: SBox<String>,
     pub posted_at: u64,
+    pub edited_at: Option<u64>,
+    pub reaction_count: ReactionCount,
+    pub tip_count: u64,
+    pub hidden: bool,
+    pub posted_by: Option<Principal>,
+    pub pinned_at: Option<u64>,
+}
+
+/// `posted_by` is set when someone other than the author published the
+/// post on the author's behalf.
+#[derive(CandidType)]
+pub struct PostQuery{
+    pub id: u64,
+    pub topic: String,
+    pub message: String,
+    pub posted_at: u64,
+    pub edited_at: Option<u64>,
     pub reaction_count: ReactionCount,
+    pub tip_count: u64,
+    pub tip_totals: Vec<TipTotal>,
+    pub hidden: bool,
+    pub posted_by: Option<Principal>,
+    pub pinned: bool,
 }
 
 impl Post{
-    pub const MAX_TOPIC_LENGTH: usize = 50;
-    pub const MAX_MESSAGE_LENGTH: usize = 700;
+    const DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
 
-    fn _post(create_post_data: CreatePostData) -> Option<Self>{
+    fn _post(create_post_data: CreatePostData, posted_by: Option<Principal>) -> Option<Self>{
         let topic = match SBox::new(create_post_data.topic){
             Ok(topic) => topic,
             Err(_) => return None
@@ -34,10 +63,16 @@ impl Post{
             Err(_) => return None
         };
         let post = Self{
+            id: 0,
             topic,
             message,
             posted_at: ic_cdk::api::time(),
+            edited_at: None,
             reaction_count: ReactionCount::default(),
+            tip_count: 0,
+            hidden: false,
+            posted_by,
+            pinned_at: None,
         };
         Some(post)
     }
@@ -52,42 +87,317 @@ impl Post{
             Err(_) => return None,
         };
         let cloned_data = Self{
+            id: self.id,
             topic,
             message,
             posted_at: self.posted_at,
-            reaction_count: self.reaction_count
+            edited_at: self.edited_at,
+            reaction_count: self.reaction_count,
+            tip_count: self.tip_count,
+            hidden: self.hidden,
+            posted_by: self.posted_by,
+            pinned_at: self.pinned_at,
         };
         Some(cloned_data)
     }
+
+    fn _to_post_query(&self, tip_totals: Vec<TipTotal>) -> PostQuery{
+        PostQuery{
+            id: self.id,
+            topic: self.topic.clone(),
+            message: self.message.clone(),
+            posted_at: self.posted_at,
+            edited_at: self.edited_at,
+            reaction_count: self.reaction_count,
+            tip_count: self.tip_count,
+            tip_totals,
+            hidden: self.hidden,
+            posted_by: self.posted_by,
+            pinned: self.pinned_at.is_some(),
+        }
+    }
 }
 
 #[derive(StableType, AsFixedSizeBytes)]
 pub struct PostState{
-    pub posts: SHashMap<SBox<String>, SVec<Post>>
+    pub posts: SHashMap<SBox<String>, SVec<Post>>,
+    pub post_authors: SHashMap<u64, SBox<String>>,
+    pub tip_totals: SHashMap<u64, SVec<TipTotal>>,
+    pub next_post_id: u64,
 }
 
 impl Default for PostState{
     fn default() -> Self {
         Self{
             posts: SHashMap::new(),
+            post_authors: SHashMap::new(),
+            tip_totals: SHashMap::new(),
+            next_post_id: 0,
         }
     }
 }
 
+impl PostState{
+    fn posts_since(&self, user: &String, since: u64) -> u64{
+        let posts = match self.posts.get(user){
+            None => return 0,
+            Some(posts) => posts
+        };
+        let mut count = 0;
+        for index in (0..posts.len()).rev(){
+            match posts.get(index){
+                Some(post) if post.posted_at > since => count += 1,
+                _ => break
+            }
+        }
+        count
+    }
+
+    fn has_recent_message(&self, user: &String, message: &String, since: u64) -> bool{
+        let posts = match self.posts.get(user){
+            None => return false,
+            Some(posts) => posts
+        };
+        for index in (0..posts.len()).rev(){
+            match posts.get(index){
+                Some(post) if post.posted_at > since => if *post.message == *message{
+                    return true
+                },
+                _ => break
+            }
+        }
+        false
+    }
+
+    fn edit_post(&mut self, user: &String, post_id: u64, create_post_data: CreatePostData, edit_window: u64, now: u64) -> Result<(), EditPostResponse>{
+        match self.post_authors.get(&post_id){
+            None => return Err(EditPostResponse::PostNotFound),
+            Some(author) if **author != *user => return Err(EditPostResponse::NotPostAuthor),
+            Some(_) => {}
+        }
+        let mut posts = match self.posts.get_mut(user){
+            None => return Err(EditPostResponse::PostNotFound),
+            Some(posts) => posts
+        };
+        let index = match posts.binary_search_by(|post| post.id.cmp(&post_id)){
+            Ok(index) => index,
+            Err(_) => return Err(EditPostResponse::PostNotFound)
+        };
+        let mut post = match posts.get_mut(index){
+            None => return Err(EditPostResponse::PostNotFound),
+            Some(post) => post
+        };
+        if now.saturating_sub(post.posted_at) > edit_window{
+            return Err(EditPostResponse::EditWindowClosed)
+        }
+        let topic = match SBox::new(create_post_data.topic){
+            Ok(topic) => topic,
+            Err(_) => return Err(EditPostResponse::FailedToAllocateMemory)
+        };
+        let message = match SBox::new(create_post_data.message){
+            Ok(message) => message,
+            Err(_) => return Err(EditPostResponse::FailedToAllocateMemory)
+        };
+        post.topic = topic;
+        post.message = message;
+        post.edited_at = Some(now);
+        Ok(())
+    }
+
+    fn post_author(&self, post_id: u64) -> Option<String>{
+        self.post_authors.get(&post_id).map(|author| author.clone())
+    }
+
+    /// The author of `post_id`, unless the post was hidden by a moderator.
+    fn visible_post_author(&self, post_id: u64) -> Option<String>{
+        let author = self.post_author(post_id)?;
+        let posts = self.posts.get(&author)?;
+        let index = posts.binary_search_by(|post| post.id.cmp(&post_id)).ok()?;
+        match posts.get(index){
+            Some(post) if !post.hidden => Some(author),
+            _ => None
+        }
+    }
+
+    /// Adds a tip of `amount` in `token` to the post's totals. Returns
+    /// `false` when the post no longer exists or memory ran out.
+    fn record_tip(&mut self, post_id: u64, token: Option<Principal>, amount: &Nat) -> bool{
+        let author = match self.post_author(post_id){
+            None => return false,
+            Some(author) => author
+        };
+        let counted = match self.posts.get_mut(&author){
+            None => false,
+            Some(mut posts) => match posts.binary_search_by(|post| post.id.cmp(&post_id)){
+                Err(_) => false,
+                Ok(index) => posts.get_mut(index).map(|mut post| post.tip_count += 1).is_some()
+            }
+        };
+        if !counted{
+            return false
+        }
+        if !self.tip_totals.contains_key(&post_id) && self.tip_totals.insert(post_id, SVec::new()).is_err(){
+            return false
+        }
+        let mut totals = match self.tip_totals.get_mut(&post_id){
+            None => return false,
+            Some(totals) => totals
+        };
+        for index in 0..totals.len(){
+            if let Some(mut total) = totals.get_mut(index){
+                if total.token == token{
+                    total.amount += amount.clone();
+                    return true
+                }
+            }
+        }
+        totals.push(TipTotal{ token, amount: amount.clone() }).is_ok()
+    }
+
+    fn tip_totals_of(&self, post_id: u64) -> Vec<TipTotal>{
+        match self.tip_totals.get(&post_id){
+            None => Vec::new(),
+            Some(totals) => totals.iter().map(|total| total.clone()).collect()
+        }
+    }
+
+    fn posts_page(&self, user: &String, cursor: u64, limit: usize) -> (Vec<PostQuery>, Option<u64>){
+        let posts = match self.posts.get(user){
+            None => return (Vec::new(), None),
+            Some(posts) => posts
+        };
+        let page: Vec<PostQuery> = posts.iter().skip(cursor as usize).take(limit).map(|post| post._to_post_query(self.tip_totals_of(post.id))).collect();
+        let next = cursor + page.len() as u64;
+        let next_cursor = if (next as usize) < posts.len(){ Some(next) }else{ None };
+        (page, next_cursor)
+    }
+
+    fn hide_post(&mut self, post_id: u64) -> bool{
+        let author = match self.post_author(post_id){
+            None => return false,
+            Some(author) => author
+        };
+        let mut posts = match self.posts.get_mut(&author){
+            None => return false,
+            Some(posts) => posts
+        };
+        let index = match posts.binary_search_by(|post| post.id.cmp(&post_id)){
+            Ok(index) => index,
+            Err(_) => return false
+        };
+        posts.get_mut(index).map(|mut post|{
+            post.hidden = true;
+            post.pinned_at = None;
+        }).is_some()
+    }
+
+    fn pinned_count(&self, user: &String) -> u64{
+        match self.posts.get(user){
+            None => 0,
+            Some(posts) => posts.iter().filter(|post| post.pinned_at.is_some()).count() as u64
+        }
+    }
+
+    /// Pins `user`'s post at `pinned_at`, as long as fewer than
+    /// `max_pinned_posts` others are pinned, or unpins it for `None`.
+    /// Pinning a pinned post moves it to the top.
+    fn set_pinned(&mut self, user: &String, post_id: u64, pinned_at: Option<u64>, max_pinned_posts: u64) -> Result<(), PinPostResponse>{
+        let pinned_count = self.pinned_count(user);
+        match self.post_authors.get(&post_id){
+            None => return Err(PinPostResponse::PostNotFound),
+            Some(author) if **author != *user => return Err(PinPostResponse::NotPostAuthor),
+            Some(_) => {}
+        }
+        let mut posts = match self.posts.get_mut(user){
+            None => return Err(PinPostResponse::PostNotFound),
+            Some(posts) => posts
+        };
+        let index = match posts.binary_search_by(|post| post.id.cmp(&post_id)){
+            Ok(index) => index,
+            Err(_) => return Err(PinPostResponse::PostNotFound)
+        };
+        let mut post = match posts.get_mut(index){
+            None => return Err(PinPostResponse::PostNotFound),
+            Some(post) => post
+        };
+        if post.hidden{
+            return Err(PinPostResponse::PostNotFound)
+        }
+        if pinned_at.is_some() && post.pinned_at.is_none() && pinned_count >= max_pinned_posts{
+            return Err(PinPostResponse::PinLimitReached)
+        }
+        post.pinned_at = pinned_at;
+        Ok(())
+    }
+
+    fn delete_post(&mut self, post_id: u64) -> bool{
+        let author = match self.post_author(post_id){
+            None => return false,
+            Some(author) => author
+        };
+        let deleted = match self.posts.get_mut(&author){
+            None => false,
+            Some(mut posts) => match posts.binary_search_by(|post| post.id.cmp(&post_id)){
+                Ok(index) => {
+                    posts.remove(index);
+                    true
+                },
+                Err(_) => false
+            }
+        };
+        self.post_authors.remove(&post_id);
+        self.tip_totals.remove(&post_id);
+        deleted
+    }
+
+    fn remove_posts_of(&mut self, user: &String){
+        let posts = match self.posts.remove(user){
+            None => return,
+            Some(posts) => posts
+        };
+        for post in posts.iter(){
+            self.post_authors.remove(&post.id);
+            self.tip_totals.remove(&post.id);
+        }
+    }
+
+    /// Visible posts of `user`, the most recently pinned first and then the
+    /// rest oldest first.
+    fn posts_by_user(&self, user: &String) -> Option<Vec<PostQuery>>{
+        let posts = self.posts.get(user)?;
+        let mut pinned: Vec<(u64, PostQuery)> = Vec::new();
+        let mut unpinned = Vec::new();
+        for post in posts.iter().filter(|post| !post.hidden){
+            let post_query = post._to_post_query(self.tip_totals_of(post.id));
+            match post.pinned_at{
+                Some(pinned_at) => pinned.push((pinned_at, post_query)),
+                None => unpinned.push(post_query)
+            }
+        }
+        pinned.sort_by(|(a, _), (b, _)| b.cmp(a));
+        Some(pinned.into_iter().map(|(_, post_query)| post_query).chain(unpinned).collect())
+    }
+}
+
 thread_local! {
     pub static POST_STATE: RefCell<PostState> = RefCell::default()
 }
 
-pub(crate) fn _allocate_storage(user: SBox<String>){
+pub(crate) fn _allocate_storage(user: SBox<String>) -> bool{
     POST_STATE.with(|state|{
         let state = &mut state.borrow_mut();
-        let _ = state.posts.insert(user, SVec::new());
+        state.posts.insert(user, SVec::new()).is_ok()
     })
 }
 
-pub(crate) fn _insert_post(user: SBox<String>, new_post: Post) -> Result<(), CreatePostResponse>{
+pub(crate) fn _insert_post(user: SBox<String>, mut new_post: Post) -> Result<(), CreatePostResponse>{
     POST_STATE.with(|state|{
         let state = &mut state.borrow_mut();
+        new_post.id = state.next_post_id;
+        let author = match SBox::new(user.clone()){
+            Ok(author) => author,
+            Err(_) => return Err(CreatePostResponse::FailedToAllocateMemory)
+        };
         let mut current_posts = match state.posts.get(&user){
             None => return Err(CreatePostResponse::UserNotRegistered),
             Some(old_posts) => {
@@ -108,48 +418,203 @@ pub(crate) fn _insert_post(user: SBox<String>, new_post: Post) -> Result<(), Cre
                 s_vec
             }
         };
+        let post_id = new_post.id;
         if current_posts.push(new_post).is_err(){
             return Err(CreatePostResponse::FailedToAllocateMemory)
         }
-        match state.posts.insert(user, current_posts){
-            Ok(_) => Ok(()),
-            Err(_) => Err(CreatePostResponse::FailedToAllocateMemory)
+        if state.posts.insert(user, current_posts).is_err(){
+            return Err(CreatePostResponse::FailedToAllocateMemory)
+        }
+        if state.post_authors.insert(post_id, author).is_err(){
+            return Err(CreatePostResponse::FailedToAllocateMemory)
         }
+        state.next_post_id += 1;
+        Ok(())
     })
 }
 
+pub(crate) fn _posts_since(user: &String, since: u64) -> u64{
+    POST_STATE.with(|state| state.borrow().posts_since(user, since))
+}
+
+pub(crate) fn _post_author(post_id: u64) -> Option<String>{
+    POST_STATE.with(|state| state.borrow().post_author(post_id))
+}
+
+pub(crate) fn _visible_post_author(post_id: u64) -> Option<String>{
+    POST_STATE.with(|state| state.borrow().visible_post_author(post_id))
+}
+
+pub(crate) fn _record_tip(post_id: u64, token: Option<Principal>, amount: &Nat) -> bool{
+    POST_STATE.with(|state| state.borrow_mut().record_tip(post_id, token, amount))
+}
+
+pub(crate) fn _posts_page(user: &String, cursor: u64, limit: usize) -> (Vec<PostQuery>, Option<u64>){
+    POST_STATE.with(|state| state.borrow().posts_page(user, cursor, limit))
+}
+
+pub(crate) fn _hide_post(post_id: u64) -> bool{
+    POST_STATE.with(|state| state.borrow_mut().hide_post(post_id))
+}
+
+pub(crate) fn _delete_post(post_id: u64) -> bool{
+    POST_STATE.with(|state| state.borrow_mut().delete_post(post_id))
+}
+
+pub(crate) fn _remove_posts_of(user: &String){
+    POST_STATE.with(|state| state.borrow_mut().remove_posts_of(user))
+}
+
+pub(crate) fn _has_recent_message(user: &String, message: &String, since: u64) -> bool{
+    POST_STATE.with(|state| state.borrow().has_recent_message(user, message, since))
+}
+
+pub(crate) fn _edit_post(user: &String, post_id: u64, create_post_data: CreatePostData, edit_window: u64) -> Result<(), EditPostResponse>{
+    let now = ic_cdk::api::time();
+    POST_STATE.with(|state| state.borrow_mut().edit_post(user, post_id, create_post_data, edit_window, now))
+}
+
 #[derive(CandidType, Deserialize)]
 pub struct CreatePostData{
     pub topic: String,
     pub message: String,
 }
 
-fn create_post_check(create_post_data: &CreatePostData) -> Result<(), CreatePostResponse>{
-    if create_post_data.topic.len() > Post::MAX_TOPIC_LENGTH{
-        Err(CreatePostResponse::TopicTooLong)
-    }else if create_post_data.message.len() > Post::MAX_MESSAGE_LENGTH{
-        Err(CreatePostResponse::MessageTooLong)
+impl CreatePostData{
+    pub(crate) fn normalized(self) -> Self{
+        Self{
+            topic: normalize_text(self.topic),
+            message: normalize_text(self.message),
+        }
+    }
+}
+
+pub(crate) enum ContentViolation{
+    TopicTooLong,
+    MessageTooLong,
+    LinksNotAllowed,
+    Rejected(ContentRejection),
+    MentionBlocked(String),
+}
+
+impl From<ContentViolation> for CreatePostResponse{
+    fn from(violation: ContentViolation) -> Self {
+        match violation{
+            ContentViolation::TopicTooLong => CreatePostResponse::TopicTooLong,
+            ContentViolation::MessageTooLong => CreatePostResponse::MessageTooLong,
+            ContentViolation::LinksNotAllowed => CreatePostResponse::LinksNotAllowed,
+            ContentViolation::Rejected(reason) => CreatePostResponse::ContentRejected{ reason },
+            ContentViolation::MentionBlocked(username) => CreatePostResponse::MentionBlocked{ username },
+        }
+    }
+}
+
+impl From<ContentViolation> for EditPostResponse{
+    fn from(violation: ContentViolation) -> Self {
+        match violation{
+            ContentViolation::TopicTooLong => EditPostResponse::TopicTooLong,
+            ContentViolation::MessageTooLong => EditPostResponse::MessageTooLong,
+            ContentViolation::LinksNotAllowed => EditPostResponse::LinksNotAllowed,
+            ContentViolation::Rejected(reason) => EditPostResponse::ContentRejected{ reason },
+            ContentViolation::MentionBlocked(username) => EditPostResponse::MentionBlocked{ username },
+        }
+    }
+}
+
+/// Number of whitespace separated words in `text` that look like links.
+fn count_links(text: &str) -> u64{
+    text.split_whitespace()
+        .map(|word| word.to_ascii_lowercase())
+        .filter(|word| word.contains("http://") || word.contains("https://") || word.contains("www."))
+        .count() as u64
+}
+
+/// Usernames mentioned as `@username` in `text`.
+fn mentioned_usernames(text: &str) -> Vec<String>{
+    text.split('@')
+        .skip(1)
+        .map(|rest| rest.chars().take_while(|c| c.is_ascii_alphanumeric() || *c == '_').collect::<String>())
+        .filter(|username| !username.is_empty())
+        .collect()
+}
+
+/// The first user mentioned in the post who has blocked `author`.
+fn blocked_mention(author: &Principal, create_post_data: &CreatePostData) -> Option<String>{
+    mentioned_usernames(&create_post_data.topic).into_iter()
+        .chain(mentioned_usernames(&create_post_data.message))
+        .find(|username| get_address(username).is_some_and(|address| _has_blocked(&address, author)))
+}
+
+fn create_post_check(create_post_data: &CreatePostData, limits: &ContentLimits, capabilities: &BadgeCapabilities, blocked_words: &[String]) -> Result<(), ContentViolation>{
+    let links = count_links(&create_post_data.topic) + count_links(&create_post_data.message);
+    if text_length(&create_post_data.topic) > limits.max_topic_length{
+        Err(ContentViolation::TopicTooLong)
+    }else if text_length(&create_post_data.message) > limits.max_message_length{
+        Err(ContentViolation::MessageTooLong)
+    }else if !capabilities.can_attach_links && links > 0{
+        Err(ContentViolation::LinksNotAllowed)
+    }else if links > limits.max_links{
+        Err(ContentViolation::Rejected(ContentRejection::TooManyLinks))
+    }else if blocked_words.iter().any(|word| contains_term(&create_post_data.topic, word) || contains_term(&create_post_data.message, word)){
+        Err(ContentViolation::Rejected(ContentRejection::BlockedWord))
     }else{
         Ok(())
     }
 }
 
+/// Every check on the content itself, under the limits of `author`'s badge.
+/// `create_post_data` must already be normalized.
+pub(crate) fn _content_check(author: &Principal, verification_badge: &VerificationBadge, create_post_data: &CreatePostData) -> Result<(), ContentViolation>{
+    create_post_check(create_post_data, &_content_limits(verification_badge), &_badge_capabilities(verification_badge), &_blocked_words())?;
+    if let Some(username) = blocked_mention(author, create_post_data){
+        return Err(ContentViolation::MentionBlocked(username))
+    }
+    Ok(())
+}
+
 #[update]
 #[candid_method(update)]
 pub fn create_post(create_post_data: CreatePostData) -> CreatePostResponse{
     let caller = ic_cdk::caller();
-    let username = match get_username(&caller){
+    if let Err(retry_after_ns) = _check_rate_limit(caller, "create_post"){
+        return CreatePostResponse::RateLimited{ retry_after_ns }
+    }
+    _create_post_for(caller, None, create_post_data)
+}
+
+/// Publishes a post of `author` under the author's badge and limits.
+/// `posted_by` is whoever acts for the author, already checked by the caller.
+pub(crate) fn _create_post_for(author: Principal, posted_by: Option<Principal>, create_post_data: CreatePostData) -> CreatePostResponse{
+    let username = match get_username(&author){
         None => return CreatePostResponse::UserNotRegistered,
         Some(username) => username
     };
+    let verification_badge = match get_verification_badge(&author){
+        None => return CreatePostResponse::UserNotRegistered,
+        Some(verification_badge) => verification_badge
+    };
+    let now = ic_cdk::api::time();
+    if let Some(until) = _suspended_until(&author, now){
+        return CreatePostResponse::Suspended{ until }
+    }
     let username = match SBox::new(username){
         Ok(name) => name,
         Err(_) => return CreatePostResponse::FailedToAllocateMemory
     };
-    if let Err(e) = create_post_check(&create_post_data){
-        return e
+    let create_post_data = create_post_data.normalized();
+    if let Err(e) = _content_check(&author, &verification_badge, &create_post_data){
+        return e.into()
+    }
+    let capabilities = _badge_capabilities(&verification_badge);
+    let duplicate_window = _duplicate_window();
+    if duplicate_window > 0 && _has_recent_message(&username, &create_post_data.message, now.saturating_sub(duplicate_window)){
+        return CreatePostResponse::ContentRejected{ reason: ContentRejection::DuplicateMessage }
+    }
+    let since = now.saturating_sub(Post::DAY);
+    if _posts_since(&username, since) >= capabilities.posts_per_day{
+        return CreatePostResponse::DailyPostLimitReached
     }
-    let post = match Post::_post(create_post_data){
+    let post = match Post::_post(create_post_data, posted_by){
         None => return CreatePostResponse::FailedToAllocateMemory,
         Some(post) => post
     };
@@ -157,4 +622,251 @@ pub fn create_post(create_post_data: CreatePostData) -> CreatePostResponse{
         return e
     }
     CreatePostResponse::Success
-}
\ No newline at end of file
+}
+
+#[update]
+#[candid_method(update)]
+pub fn edit_post(post_id: u64, create_post_data: CreatePostData) -> EditPostResponse{
+    let caller = ic_cdk::caller();
+    if let Err(retry_after_ns) = _check_rate_limit(caller, "edit_post"){
+        return EditPostResponse::RateLimited{ retry_after_ns }
+    }
+    _edit_post_for(caller, post_id, create_post_data)
+}
+
+/// Edits a post of `author`, like `_create_post_for`.
+pub(crate) fn _edit_post_for(author: Principal, post_id: u64, create_post_data: CreatePostData) -> EditPostResponse{
+    let username = match get_username(&author){
+        None => return EditPostResponse::UserNotRegistered,
+        Some(username) => username
+    };
+    let verification_badge = match get_verification_badge(&author){
+        None => return EditPostResponse::UserNotRegistered,
+        Some(verification_badge) => verification_badge
+    };
+    if let Some(until) = _suspended_until(&author, ic_cdk::api::time()){
+        return EditPostResponse::Suspended{ until }
+    }
+    let create_post_data = create_post_data.normalized();
+    if let Err(e) = _content_check(&author, &verification_badge, &create_post_data){
+        return e.into()
+    }
+    let capabilities = _badge_capabilities(&verification_badge);
+    if let Err(e) = _edit_post(&username, post_id, create_post_data, capabilities.edit_window){
+        return e
+    }
+    EditPostResponse::Success
+}
+
+/// Pins one of the caller's posts to the top of their profile, up to the
+/// `max_pinned_posts` of their badge.
+#[update]
+#[candid_method(update)]
+pub fn pin_post(post_id: u64) -> PinPostResponse{
+    let caller = ic_cdk::caller();
+    if let Err(retry_after_ns) = _check_rate_limit(caller, "pin_post"){
+        return PinPostResponse::RateLimited{ retry_after_ns }
+    }
+    let (username, verification_badge) = match (get_username(&caller), get_verification_badge(&caller)){
+        (Some(username), Some(verification_badge)) => (username, verification_badge),
+        _ => return PinPostResponse::UserNotRegistered
+    };
+    let now = ic_cdk::api::time();
+    if let Some(until) = _suspended_until(&caller, now){
+        return PinPostResponse::Suspended{ until }
+    }
+    let max_pinned_posts = _badge_capabilities(&verification_badge).max_pinned_posts;
+    if max_pinned_posts == 0{
+        return PinPostResponse::PinningNotAllowed
+    }
+    match POST_STATE.with(|state| state.borrow_mut().set_pinned(&username, post_id, Some(now), max_pinned_posts)){
+        Ok(()) => PinPostResponse::Success,
+        Err(e) => e
+    }
+}
+
+#[update]
+#[candid_method(update)]
+pub fn unpin_post(post_id: u64) -> PinPostResponse{
+    let caller = ic_cdk::caller();
+    if let Err(retry_after_ns) = _check_rate_limit(caller, "unpin_post"){
+        return PinPostResponse::RateLimited{ retry_after_ns }
+    }
+    let username = match get_username(&caller){
+        None => return PinPostResponse::UserNotRegistered,
+        Some(username) => username
+    };
+    if let Some(until) = _suspended_until(&caller, ic_cdk::api::time()){
+        return PinPostResponse::Suspended{ until }
+    }
+    match POST_STATE.with(|state| state.borrow_mut().set_pinned(&username, post_id, None, 0)){
+        Ok(()) => PinPostResponse::Success,
+        Err(e) => e
+    }
+}
+
+/// `user`'s posts as `viewer` sees them, which is none at all once the
+/// viewer has muted `author` or `author` has blocked the viewer.
+fn _posts_by_user_for(viewer: &Principal, author: &Principal, user: &String) -> Option<Vec<PostQuery>>{
+    if _has_muted(viewer, author) || _has_blocked(author, viewer){
+        return Some(Vec::new())
+    }
+    POST_STATE.with(|state| state.borrow().posts_by_user(user))
+}
+
+#[query]
+#[candid_method(query)]
+pub fn get_posts_by_user(username: String) -> Option<Vec<PostQuery>>{
+    let caller = ic_cdk::caller();
+    // posts are stored under the registered spelling of the name
+    let author = get_address(&username)?;
+    let user = get_username(&author)?;
+    _posts_by_user_for(&caller, &author, &user)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::RateLimit, relationship::RELATIONSHIP_STATE};
+    use crate::testing::{principal, init_memory};
+
+    const LIMITS: ContentLimits = ContentLimits{ max_topic_length: 50, max_message_length: 700, max_links: 1 };
+
+    fn capabilities(can_attach_links: bool) -> BadgeCapabilities {
+        BadgeCapabilities{ posts_per_day: 10, max_pinned_posts: 0, edit_window: 0, can_attach_links, can_schedule_posts: false, rate_limit: RateLimit{ capacity: 0, refill_interval: 0 } }
+    }
+
+    fn post_data(message: &str) -> CreatePostData {
+        CreatePostData{ topic: "topic".to_string(), message: message.to_string() }
+    }
+
+    #[test]
+    fn links_are_counted_against_the_badge_limit() {
+        let one_link = post_data("see https://example.com");
+        let two_links = post_data("see https://example.com and www.example.org");
+        assert!(matches!(create_post_check(&one_link, &LIMITS, &capabilities(false), &[]), Err(ContentViolation::LinksNotAllowed)));
+        assert!(create_post_check(&one_link, &LIMITS, &capabilities(true), &[]).is_ok());
+        assert!(matches!(create_post_check(&two_links, &LIMITS, &capabilities(true), &[]), Err(ContentViolation::Rejected(ContentRejection::TooManyLinks))));
+    }
+
+    #[test]
+    fn blocked_words_reject_the_post() {
+        let blocked_words = vec!["free money".to_string()];
+        assert!(matches!(create_post_check(&post_data("Get FREE money today"), &LIMITS, &capabilities(false), &blocked_words), Err(ContentViolation::Rejected(ContentRejection::BlockedWord))));
+        assert!(create_post_check(&post_data("money is never free"), &LIMITS, &capabilities(false), &blocked_words).is_ok());
+    }
+
+    #[test]
+    fn mentions_end_at_the_first_character_outside_a_username() {
+        assert_eq!(mentioned_usernames("thanks @alice_1, and @bob!"), vec!["alice_1".to_string(), "bob".to_string()]);
+        assert!(mentioned_usernames("mail me at @ noon").is_empty());
+    }
+
+    #[test]
+    fn lengths_count_scalar_values_of_nfc_text() {
+        let nepali = post_data(&"नमस्ते ".repeat(100));
+        assert!(nepali.message.len() > 700);
+        assert!(create_post_check(&nepali, &LIMITS, &capabilities(false), &[]).is_ok());
+        assert!(matches!(create_post_check(&post_data(&"🙂".repeat(701)), &LIMITS, &capabilities(false), &[]), Err(ContentViolation::MessageTooLong)));
+        let decomposed = post_data(&"e\u{301}".repeat(700)).normalized();
+        assert_eq!(decomposed.message, "\u{e9}".repeat(700));
+        assert!(create_post_check(&decomposed, &LIMITS, &capabilities(false), &[]).is_ok());
+    }
+
+    fn stored_post(id: u64) -> Post {
+        Post{
+            id,
+            topic: SBox::new("topic".to_string()).unwrap(),
+            message: SBox::new(format!("post {id}")).unwrap(),
+            posted_at: id,
+            edited_at: None,
+            reaction_count: ReactionCount::default(),
+            tip_count: 0,
+            hidden: false,
+            posted_by: None,
+            pinned_at: None,
+        }
+    }
+
+    fn post_state_of(user: &str, ids: &[u64]) -> PostState {
+        let mut state = PostState::default();
+        let mut posts = SVec::new();
+        for id in ids {
+            posts.push(stored_post(*id)).ok().unwrap();
+            state.post_authors.insert(*id, SBox::new(user.to_string()).unwrap()).ok().unwrap();
+        }
+        state.posts.insert(SBox::new(user.to_string()).unwrap(), posts).ok().unwrap();
+        state
+    }
+
+    fn post_ids(state: &PostState, user: &str) -> Vec<u64> {
+        state.posts_by_user(&user.to_string()).unwrap().iter().map(|post| post.id).collect()
+    }
+
+    #[test]
+    fn pinned_posts_come_first_most_recently_pinned_on_top() {
+        init_memory();
+        let alice = "alice".to_string();
+        let mut state = post_state_of("alice", &[0, 1, 2, 3]);
+        assert!(state.set_pinned(&alice, 1, Some(10), 2).is_ok());
+        assert!(state.set_pinned(&alice, 3, Some(20), 2).is_ok());
+        assert_eq!(post_ids(&state, "alice"), vec![3, 1, 0, 2]);
+        assert!(matches!(state.set_pinned(&alice, 2, Some(30), 2), Err(PinPostResponse::PinLimitReached)));
+        assert!(state.set_pinned(&alice, 1, Some(30), 2).is_ok());
+        assert_eq!(post_ids(&state, "alice"), vec![1, 3, 0, 2]);
+        assert!(matches!(state.set_pinned(&"bob".to_string(), 1, None, 0), Err(PinPostResponse::NotPostAuthor)));
+    }
+
+    #[test]
+    fn muted_authors_are_left_out_of_the_muters_timeline() {
+        init_memory();
+        let (viewer, author) = (principal(1), principal(2));
+        let alice = "alice".to_string();
+        POST_STATE.with(|state| *state.borrow_mut() = post_state_of("alice", &[0, 1]));
+        assert_eq!(_posts_by_user_for(&viewer, &author, &alice).unwrap().len(), 2);
+        RELATIONSHIP_STATE.with(|state| state.borrow_mut().muted.add(viewer, author));
+        assert!(_posts_by_user_for(&viewer, &author, &alice).unwrap().is_empty());
+        assert_eq!(_posts_by_user_for(&author, &author, &alice).unwrap().len(), 2);
+        RELATIONSHIP_STATE.with(|state| state.borrow_mut().muted.remove(&viewer, &author));
+        assert_eq!(_posts_by_user_for(&viewer, &author, &alice).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn deleting_or_hiding_a_pinned_post_unpins_it() {
+        init_memory();
+        let alice = "alice".to_string();
+        let mut state = post_state_of("alice", &[0, 1, 2]);
+        assert!(state.set_pinned(&alice, 1, Some(10), 1).is_ok());
+        assert!(state.delete_post(1));
+        assert_eq!(state.pinned_count(&alice), 0);
+        assert!(state.set_pinned(&alice, 2, Some(20), 1).is_ok());
+        assert!(state.hide_post(2));
+        assert_eq!(state.pinned_count(&alice), 0);
+        assert!(matches!(state.set_pinned(&alice, 2, Some(30), 1), Err(PinPostResponse::PostNotFound)));
+        assert_eq!(post_ids(&state, "alice"), vec![0]);
+    }
+
+    #[test]
+    fn hidden_posts_have_no_visible_author() {
+        init_memory();
+        let mut state = post_state_of("alice", &[0, 1]);
+        assert!(state.hide_post(1));
+        assert_eq!(state.visible_post_author(0), Some("alice".to_string()));
+        assert_eq!(state.visible_post_author(1), None);
+        assert_eq!(state.post_author(1), Some("alice".to_string()));
+        assert_eq!(state.visible_post_author(2), None);
+    }
+
+    #[test]
+    fn blocked_viewers_do_not_see_the_blockers_posts() {
+        init_memory();
+        let (viewer, author) = (principal(1), principal(2));
+        let alice = "alice".to_string();
+        POST_STATE.with(|state| *state.borrow_mut() = post_state_of("alice", &[0, 1]));
+        RELATIONSHIP_STATE.with(|state| state.borrow_mut().blocked.add(author, viewer));
+        assert_eq!(_posts_by_user_for(&viewer, &author, &alice).unwrap().len(), 0);
+        assert_eq!(_posts_by_user_for(&principal(3), &author, &alice).unwrap().len(), 2);
+        // blocking only works one way
+        assert_eq!(_posts_by_user_for(&author, &viewer, &alice).unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,135 @@
+use std::{cell::RefCell, collections::HashMap};
+
+use candid::Principal;
+
+use crate::{config::{RateLimit, _badge_capabilities}, registration::get_verification_badge, verification_status::VerificationBadge};
+
+/// Tokens left for one principal on one endpoint. Tokens come back one per
+/// `RateLimit::refill_interval`, counted from `refilled_at`.
+struct Bucket{
+    tokens: u64,
+    refilled_at: u64,
+}
+
+impl Bucket{
+    fn full(limit: &RateLimit, now: u64) -> Self{
+        Self{ tokens: limit.capacity, refilled_at: now }
+    }
+
+    fn refill(&mut self, limit: &RateLimit, now: u64){
+        let refilled = now.saturating_sub(self.refilled_at) / limit.refill_interval;
+        self.tokens = self.tokens.saturating_add(refilled).min(limit.capacity);
+        if self.tokens == limit.capacity{
+            self.refilled_at = now;
+        }else{
+            self.refilled_at += refilled * limit.refill_interval;
+        }
+    }
+
+    /// Takes a token, or returns how many nanoseconds until one is back.
+    fn take(&mut self, limit: &RateLimit, now: u64) -> Result<(), u64>{
+        self.refill(limit, now);
+        if self.tokens == 0{
+            return Err(self.refilled_at + limit.refill_interval - now)
+        }
+        self.tokens -= 1;
+        Ok(())
+    }
+}
+
+/// Buckets live on the heap: losing them on upgrade only resets the limits.
+#[derive(Default)]
+pub struct RateLimiter{
+    buckets: HashMap<(Principal, &'static str), Bucket>,
+}
+
+impl RateLimiter{
+    fn take(&mut self, caller: Principal, endpoint: &'static str, limit: &RateLimit, now: u64) -> Result<(), u64>{
+        if limit.refill_interval == 0{
+            return Ok(())
+        }
+        self.buckets.entry((caller, endpoint))
+            .or_insert_with(|| Bucket::full(limit, now))
+            .take(limit, now)
+    }
+
+    /// Drops buckets idle long enough to have refilled completely, which
+    /// behave the same as no bucket at all.
+    fn prune(&mut self, now: u64, longest_refill: u64){
+        self.buckets.retain(|_, bucket| now.saturating_sub(bucket.refilled_at) < longest_refill);
+    }
+}
+
+thread_local! {
+    static RATE_LIMITER: RefCell<RateLimiter> = RefCell::default();
+}
+
+/// Spends one of the caller's calls to `endpoint` under the limits of their
+/// badge; callers without an account get the regular limits. Returns the
+/// nanoseconds to wait when none are left.
+pub(crate) fn _check_rate_limit(caller: Principal, endpoint: &'static str) -> Result<(), u64>{
+    let verification_badge = get_verification_badge(&caller).unwrap_or(VerificationBadge::RegularVerified);
+    let limit = _badge_capabilities(&verification_badge).rate_limit;
+    let now = ic_cdk::api::time();
+    RATE_LIMITER.with(|state| state.borrow_mut().take(caller, endpoint, &limit, now))
+}
+
+pub(crate) fn _prune_rate_limits(now: u64){
+    let longest_refill = [VerificationBadge::RegularVerified, VerificationBadge::PlusVerified, VerificationBadge::BusinessVerified].iter()
+        .map(|verification_badge|{
+            let limit = _badge_capabilities(verification_badge).rate_limit;
+            limit.capacity.saturating_mul(limit.refill_interval)
+        })
+        .max()
+        .unwrap_or_default();
+    RATE_LIMITER.with(|state| state.borrow_mut().prune(now, longest_refill))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::principal;
+
+    const SECOND: u64 = 1_000_000_000;
+    const LIMIT: RateLimit = RateLimit{ capacity: 2, refill_interval: 10 * SECOND };
+
+    #[test]
+    fn buckets_refill_one_token_per_interval() {
+        let mut limiter = RateLimiter::default();
+        assert!(limiter.take(principal(2), "create_post", &LIMIT, 0).is_ok());
+        assert!(limiter.take(principal(2), "create_post", &LIMIT, 0).is_ok());
+        assert_eq!(limiter.take(principal(2), "create_post", &LIMIT, 4 * SECOND), Err(6 * SECOND));
+        assert!(limiter.take(principal(2), "create_post", &LIMIT, 10 * SECOND).is_ok());
+        assert_eq!(limiter.take(principal(2), "create_post", &LIMIT, 12 * SECOND), Err(8 * SECOND));
+    }
+
+    #[test]
+    fn buckets_are_kept_per_principal_and_endpoint() {
+        let mut limiter = RateLimiter::default();
+        for _ in 0..2 {
+            assert!(limiter.take(principal(2), "create_post", &LIMIT, 0).is_ok());
+        }
+        assert!(limiter.take(principal(2), "create_post", &LIMIT, 0).is_err());
+        assert!(limiter.take(principal(2), "edit_post", &LIMIT, 0).is_ok());
+        assert!(limiter.take(principal(3), "create_post", &LIMIT, 0).is_ok());
+    }
+
+    #[test]
+    fn a_zero_refill_interval_disables_the_limit() {
+        let mut limiter = RateLimiter::default();
+        let unlimited = RateLimit{ capacity: 0, refill_interval: 0 };
+        for _ in 0..100 {
+            assert!(limiter.take(principal(2), "create_post", &unlimited, 0).is_ok());
+        }
+    }
+
+    #[test]
+    fn idle_buckets_are_pruned() {
+        let mut limiter = RateLimiter::default();
+        limiter.take(principal(2), "create_post", &LIMIT, 0).unwrap();
+        limiter.prune(5 * SECOND, 20 * SECOND);
+        assert_eq!(limiter.buckets.len(), 1);
+        limiter.prune(20 * SECOND, 20 * SECOND);
+        assert!(limiter.buckets.is_empty());
+    }
+}
\ No newline at end of file
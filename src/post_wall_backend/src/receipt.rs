@@ -0,0 +1,197 @@
+use std::cell::RefCell;
+
+use candid::{CandidType, candid_method, Nat, Principal};
+use ic_cdk_macros::*;
+use ic_stable_memory::{derive::{AsFixedSizeBytes, StableType}, collections::{SHashMap, SVec}};
+
+use crate::{verification_status::VerificationBadge, treasury::can_manage_treasury, response::ReceiptsResponse};
+
+#[derive(CandidType, StableType, AsFixedSizeBytes, Clone, PartialEq)]
+pub enum ReceiptKind{
+    Registration,
+    BadgeUpgrade,
+    Renewal,
+    Tip{ post_id: u64 },
+}
+
+/// Proof of a paid action. `token` is the ICRC-1 ledger the amount was paid
+/// on, `None` for ICP, and `block_index` the block on that ledger.
+#[derive(CandidType, StableType, AsFixedSizeBytes, Clone)]
+pub struct Receipt{
+    pub id: u64,
+    pub payer: Principal,
+    pub kind: ReceiptKind,
+    pub block_index: u64,
+    pub amount: Nat,
+    pub token: Option<Principal>,
+    pub verification_badge: Option<VerificationBadge>,
+    pub paid_at: u64,
+    pub refunded: bool,
+}
+
+#[derive(CandidType)]
+pub struct ReceiptPage{
+    pub receipts: Vec<Receipt>,
+    pub next_cursor: Option<u64>,
+}
+
+#[derive(StableType, AsFixedSizeBytes)]
+pub struct ReceiptState{
+    pub receipts: SVec<Receipt>,
+    pub receipts_by_payer: SHashMap<Principal, SVec<u64>>,
+}
+
+impl Default for ReceiptState{
+    fn default() -> Self {
+        Self{
+            receipts: SVec::new(),
+            receipts_by_payer: SHashMap::new(),
+        }
+    }
+}
+
+impl ReceiptState{
+    pub const MAX_PAGE_SIZE: usize = 100;
+
+    pub(crate) fn issue(&mut self, mut receipt: Receipt) -> Option<u64>{
+        let id = self.receipts.len() as u64;
+        let payer = receipt.payer;
+        receipt.id = id;
+        if self.receipts.push(receipt).is_err(){
+            return None
+        }
+        let indexed = if self.receipts_by_payer.contains_key(&payer){
+            self.receipts_by_payer.get_mut(&payer).is_some_and(|mut ids| ids.push(id).is_ok())
+        }else{
+            let mut ids = SVec::new();
+            ids.push(id).is_ok() && self.receipts_by_payer.insert(payer, ids).is_ok()
+        };
+        if !indexed{
+            self.receipts.pop();
+            return None
+        }
+        Some(id)
+    }
+
+    pub(crate) fn get(&self, id: u64) -> Option<Receipt>{
+        self.receipts.get(id as usize).map(|receipt| receipt.clone())
+    }
+
+    pub(crate) fn set_refunded(&mut self, ids: &[u64], refunded: bool){
+        for id in ids{
+            if let Some(mut receipt) = self.receipts.get_mut(*id as usize){
+                receipt.refunded = refunded;
+            }
+        }
+    }
+
+    pub(crate) fn receipts_of(&self, payer: &Principal) -> Vec<Receipt>{
+        match self.receipts_by_payer.get(payer){
+            None => Vec::new(),
+            Some(ids) => ids.iter().filter_map(|id| self.get(*id)).collect()
+        }
+    }
+
+    fn page_of(&self, payer: &Principal, cursor: u64, limit: usize) -> ReceiptPage{
+        let ids = match self.receipts_by_payer.get(payer){
+            None => return ReceiptPage{ receipts: Vec::new(), next_cursor: None },
+            Some(ids) => ids
+        };
+        let limit = limit.min(Self::MAX_PAGE_SIZE);
+        let receipts: Vec<Receipt> = ids.iter().skip(cursor as usize).take(limit).filter_map(|id| self.get(*id)).collect();
+        let next = cursor + receipts.len() as u64;
+        let next_cursor = if (next as usize) < ids.len(){ Some(next) }else{ None };
+        ReceiptPage{ receipts, next_cursor }
+    }
+
+    fn range(&self, start: u64, end: u64) -> Vec<Receipt>{
+        let end = end.min(start.saturating_add(Self::MAX_PAGE_SIZE as u64));
+        (start..end).map_while(|id| self.get(id)).collect()
+    }
+}
+
+thread_local! {
+    pub static RECEIPT_STATE: RefCell<ReceiptState> = RefCell::default();
+}
+
+pub(crate) fn _issue_receipt(payer: Principal, kind: ReceiptKind, block_index: u64, amount: Nat, token: Option<Principal>, verification_badge: Option<VerificationBadge>) -> Option<u64>{
+    let receipt = Receipt{
+        id: 0,
+        payer,
+        kind,
+        block_index,
+        amount,
+        token,
+        verification_badge,
+        paid_at: ic_cdk::api::time(),
+        refunded: false,
+    };
+    RECEIPT_STATE.with(|state| state.borrow_mut().issue(receipt))
+}
+
+#[query]
+#[candid_method(query)]
+pub fn get_my_receipts(cursor: Option<u64>, limit: u64) -> ReceiptPage{
+    let caller = ic_cdk::caller();
+    RECEIPT_STATE.with(|state| state.borrow().page_of(&caller, cursor.unwrap_or_default(), limit as usize))
+}
+
+/// Receipts with ids in `start..end`, at most `ReceiptState::MAX_PAGE_SIZE`
+/// of them.
+#[query]
+#[candid_method(query)]
+pub fn get_receipts(start: u64, end: u64) -> ReceiptsResponse{
+    let caller = ic_cdk::caller();
+    if !can_manage_treasury(&caller){
+        return ReceiptsResponse::Unauthorized
+    }
+    ReceiptsResponse::Success(RECEIPT_STATE.with(|state| state.borrow().range(start, end)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{principal, init_memory};
+
+    fn receipt(payer: Principal, block_index: u64) -> Receipt {
+        Receipt{
+            id: 0,
+            payer,
+            kind: ReceiptKind::Registration,
+            block_index,
+            amount: Nat::from(100),
+            token: None,
+            verification_badge: Some(VerificationBadge::PlusVerified),
+            paid_at: 0,
+            refunded: false,
+        }
+    }
+
+    #[test]
+    fn receipts_are_paged_per_payer() {
+        init_memory();
+        let mut state = ReceiptState::default();
+        for block_index in 0..5 {
+            state.issue(receipt(principal(2), block_index)).unwrap();
+            state.issue(receipt(principal(3), block_index)).unwrap();
+        }
+        let page = state.page_of(&principal(2), 0, 3);
+        assert_eq!(page.receipts.iter().map(|receipt| receipt.id).collect::<Vec<_>>(), vec![0, 2, 4]);
+        assert_eq!(page.next_cursor, Some(3));
+        let page = state.page_of(&principal(2), 3, 3);
+        assert_eq!(page.receipts.iter().map(|receipt| receipt.block_index).collect::<Vec<_>>(), vec![3, 4]);
+        assert_eq!(page.next_cursor, None);
+        assert!(state.page_of(&principal(4), 0, 3).receipts.is_empty());
+    }
+
+    #[test]
+    fn receipt_ranges_stop_at_the_last_receipt() {
+        init_memory();
+        let mut state = ReceiptState::default();
+        for block_index in 0..3 {
+            state.issue(receipt(principal(2), block_index)).unwrap();
+        }
+        assert_eq!(state.range(1, 10).iter().map(|receipt| receipt.id).collect::<Vec<_>>(), vec![1, 2]);
+        assert!(state.range(5, 10).is_empty());
+    }
+}
\ No newline at end of file
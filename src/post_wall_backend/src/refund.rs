@@ -0,0 +1,198 @@
+use candid::{candid_method, Principal};
+use ic_cdk_macros::*;
+use ic_ledger_types::{AccountIdentifier, Memo, Timestamp, Tokens, TransferArgs, TransferError, DEFAULT_FEE};
+
+use crate::{payment::{Ledger, IcpLedger, lock_deposit, unlock_deposit, nat_to_e8s}, receipt::RECEIPT_STATE, treasury::can_manage_treasury, registration::get_username, config::_refund_grace_period, utils::subaccount_generator, response::{ReclaimDepositResponse, RefundVerificationResponse}};
+
+/// Ids and ICP amounts of the verification fees `payer` paid since
+/// `paid_since` that have not been refunded yet. Fees paid in other tokens
+/// are not refunded through `refund_verification`.
+fn refundable_payments(payer: &Principal, paid_since: u64) -> Vec<(u64, u64)>{
+    RECEIPT_STATE.with(|state| state.borrow().receipts_of(payer))
+        .into_iter()
+        .filter(|receipt| receipt.verification_badge.is_some() && receipt.token.is_none() && !receipt.refunded && receipt.paid_at >= paid_since)
+        .filter_map(|receipt| nat_to_e8s(&receipt.amount).map(|amount| (receipt.id, amount)))
+        .collect()
+}
+
+/// Sends everything in the caller's deposit subaccount, minus the ledger
+/// fee, to `to`.
+pub(crate) async fn _reclaim_deposit<L: Ledger>(ledger: &L, canister: Principal, caller: Principal, to: AccountIdentifier, now: u64) -> ReclaimDepositResponse{
+    if !lock_deposit(caller){
+        return ReclaimDepositResponse::PaymentInProgress
+    }
+    let response = _sweep_deposit(ledger, canister, caller, to, now).await;
+    unlock_deposit(&caller);
+    response
+}
+
+async fn _sweep_deposit<L: Ledger>(ledger: &L, canister: Principal, caller: Principal, to: AccountIdentifier, now: u64) -> ReclaimDepositResponse{
+    let subaccount = subaccount_generator(&caller);
+    let balance = match ledger.account_balance(AccountIdentifier::new(&canister, &subaccount)).await{
+        Ok(balance) => balance,
+        Err(message) => return ReclaimDepositResponse::LedgerError(message)
+    };
+    if balance.e8s() <= DEFAULT_FEE.e8s(){
+        return ReclaimDepositResponse::NothingToReclaim
+    }
+    let amount = Tokens::from_e8s(balance.e8s() - DEFAULT_FEE.e8s());
+    let transfer_args = TransferArgs{
+        memo: Memo(0),
+        amount,
+        fee: DEFAULT_FEE,
+        from_subaccount: Some(subaccount),
+        to,
+        created_at_time: Some(Timestamp{ timestamp_nanos: now }),
+    };
+    match ledger.transfer(transfer_args).await{
+        Ok(Ok(block_index)) => ReclaimDepositResponse::Success{ block_index, amount },
+        Ok(Err(e)) => ReclaimDepositResponse::TransferFailed(e.to_string()),
+        Err(message) => ReclaimDepositResponse::LedgerError(message),
+    }
+}
+
+/// Refunds, from the treasury, every verification fee `payer` paid within the
+/// grace period, provided `payer` no longer has an account.
+pub(crate) async fn _refund_verification<L: Ledger>(ledger: &L, caller: Principal, payer: Principal, to: AccountIdentifier, now: u64) -> RefundVerificationResponse{
+    if !can_manage_treasury(&caller){
+        return RefundVerificationResponse::Unauthorized
+    }
+    if get_username(&payer).is_some(){
+        return RefundVerificationResponse::AccountStillRegistered
+    }
+    let paid_since = now.saturating_sub(_refund_grace_period());
+    let payments = refundable_payments(&payer, paid_since);
+    if payments.is_empty(){
+        return RefundVerificationResponse::NothingToRefund
+    }
+    let ids: Vec<u64> = payments.iter().map(|(id, _)| *id).collect();
+    let amount = Tokens::from_e8s(payments.iter().map(|(_, amount)| amount).sum());
+    // mark the payments before the ledger call so a concurrent refund skips them
+    RECEIPT_STATE.with(|state| state.borrow_mut().set_refunded(&ids, true));
+    let transfer_args = TransferArgs{
+        memo: Memo(0),
+        amount,
+        fee: DEFAULT_FEE,
+        from_subaccount: None,
+        to,
+        created_at_time: Some(Timestamp{ timestamp_nanos: now }),
+    };
+    let response = match ledger.transfer(transfer_args).await{
+        Ok(Ok(block_index)) => return RefundVerificationResponse::Success{ block_index, amount },
+        Ok(Err(TransferError::InsufficientFunds{ balance })) => RefundVerificationResponse::InsufficientFunds{ balance },
+        Ok(Err(e)) => RefundVerificationResponse::TransferFailed(e.to_string()),
+        Err(message) => RefundVerificationResponse::LedgerError(message),
+    };
+    RECEIPT_STATE.with(|state| state.borrow_mut().set_refunded(&ids, false));
+    response
+}
+
+#[update]
+#[candid_method(update)]
+pub async fn reclaim_deposit(to_account: AccountIdentifier) -> ReclaimDepositResponse{
+    let caller = ic_cdk::caller();
+    _reclaim_deposit(&IcpLedger::configured(), ic_cdk::id(), caller, to_account, ic_cdk::api::time()).await
+}
+
+#[update]
+#[candid_method(update)]
+pub async fn refund_verification(payer: Principal, to_account: AccountIdentifier) -> RefundVerificationResponse{
+    let caller = ic_cdk::caller();
+    _refund_verification(&IcpLedger::configured(), caller, payer, to_account, ic_cdk::api::time()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{init::_change_authorty, mock_ledger::{block_on, MockLedger}, receipt::{Receipt, ReceiptKind}, verification_status::VerificationBadge};
+    use candid::Nat;
+    use ic_ledger_types::DEFAULT_SUBACCOUNT;
+    use crate::testing::{principal, init_memory};
+
+    const CANISTER: u8 = 100;
+    const DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+    fn account(id: u8) -> AccountIdentifier {
+        AccountIdentifier::new(&principal(id), &DEFAULT_SUBACCOUNT)
+    }
+
+    fn setup() -> MockLedger {
+        init_memory();
+        _change_authorty(principal(1));
+        let ledger = MockLedger::new(principal(CANISTER));
+        ledger.deposit(account(CANISTER), 1_000_000);
+        ledger
+    }
+
+    fn record_payment(payer: Principal, amount: u64, paid_at: u64) {
+        let receipt = Receipt{
+            id: 0,
+            payer,
+            kind: ReceiptKind::Registration,
+            block_index: 0,
+            amount: Nat::from(amount),
+            token: None,
+            verification_badge: Some(VerificationBadge::PlusVerified),
+            paid_at,
+            refunded: false,
+        };
+        RECEIPT_STATE.with(|state| state.borrow_mut().issue(receipt).unwrap());
+    }
+
+    #[test]
+    fn reclaim_deposit_sweeps_the_subaccount_minus_the_fee() {
+        let ledger = setup();
+        let deposit = AccountIdentifier::new(&principal(CANISTER), &subaccount_generator(&principal(2)));
+        ledger.deposit(deposit, 50_000);
+        let response = block_on(_reclaim_deposit(&ledger, principal(CANISTER), principal(2), account(2), 10));
+        assert!(matches!(response, ReclaimDepositResponse::Success{ amount, .. } if amount.e8s() == 50_000 - DEFAULT_FEE.e8s()));
+        assert_eq!(ledger.balance(&deposit), 0);
+        assert_eq!(ledger.balance(&account(2)), 50_000 - DEFAULT_FEE.e8s());
+    }
+
+    #[test]
+    fn reclaim_deposit_ignores_balances_that_cannot_cover_the_fee() {
+        let ledger = setup();
+        let deposit = AccountIdentifier::new(&principal(CANISTER), &subaccount_generator(&principal(2)));
+        ledger.deposit(deposit, DEFAULT_FEE.e8s());
+        let response = block_on(_reclaim_deposit(&ledger, principal(CANISTER), principal(2), account(2), 10));
+        assert!(matches!(response, ReclaimDepositResponse::NothingToReclaim));
+        assert_eq!(ledger.transfer_count(), 0);
+    }
+
+    #[test]
+    fn refund_verification_pays_back_payments_within_the_grace_period_once() {
+        let ledger = setup();
+        let now = 30 * DAY;
+        record_payment(principal(2), 100_000, now - DAY);
+        record_payment(principal(2), 300_000, now - 29 * DAY);
+        record_payment(principal(3), 200_000, now - DAY);
+        let response = block_on(_refund_verification(&ledger, principal(1), principal(2), account(2), now));
+        assert!(matches!(response, RefundVerificationResponse::Success{ amount, .. } if amount.e8s() == 100_000));
+        assert_eq!(ledger.balance(&account(2)), 100_000);
+        let response = block_on(_refund_verification(&ledger, principal(1), principal(2), account(2), now));
+        assert!(matches!(response, RefundVerificationResponse::NothingToRefund));
+    }
+
+    #[test]
+    fn refund_verification_requires_the_authority_or_a_treasurer() {
+        let ledger = setup();
+        record_payment(principal(2), 100_000, 0);
+        let response = block_on(_refund_verification(&ledger, principal(2), principal(2), account(2), DAY));
+        assert!(matches!(response, RefundVerificationResponse::Unauthorized));
+        assert_eq!(ledger.transfer_count(), 0);
+    }
+
+    #[test]
+    fn failed_refunds_can_be_retried() {
+        let ledger = MockLedger::new(principal(CANISTER));
+        init_memory();
+        _change_authorty(principal(1));
+        record_payment(principal(2), 100_000, 0);
+        let response = block_on(_refund_verification(&ledger, principal(1), principal(2), account(2), DAY));
+        assert!(matches!(response, RefundVerificationResponse::InsufficientFunds{ .. }));
+        ledger.deposit(account(CANISTER), 1_000_000);
+        let response = block_on(_refund_verification(&ledger, principal(1), principal(2), account(2), DAY));
+        assert!(matches!(response, RefundVerificationResponse::Success{ .. }));
+    }
+}
\ No newline at end of file
@@ -1,10 +1,10 @@
-use std::cell::RefCell;
+use std::{cell::RefCell, collections::BTreeSet};
 
 use candid::{CandidType, candid_method, Deserialize, Principal};
 use ic_cdk_macros::*;
-use ic_stable_memory::{derive::{AsFixedSizeBytes, StableType}, SBox, collections::SVec};
+use ic_stable_memory::{derive::{AsFixedSizeBytes, StableType}, SBox, collections::{SHashMap, SVec}};
 
-use crate::{verification_status::VerificationBadge, response::RegistrationResponse,};
+use crate::{verification_status::{VerificationBadge, _add_user, _verification_expiry, _verification_charge_in}, response::{RegistrationResponse, UpdateProfileResponse}, config::{_profile_limits, _minimum_age}, payment::{collect_payment, refund_payment, PaymentError}, post::_allocate_storage, receipt::{_issue_receipt, ReceiptKind}, rate_limit::_check_rate_limit, username::{fold_username, username_check}, utils::{text_length, normalize_text, date_of, days_in_month, NANOSECONDS_PER_DAY}};
 
 #[derive(CandidType, AsFixedSizeBytes, StableType, Clone, Deserialize)]
 pub enum Gender{
@@ -13,6 +13,15 @@ pub enum Gender{
     RatherNotToSay,
 }
 
+/// Whether an account may write. A suspension ends on its own at `until`,
+/// a ban only through `unban_user`.
+#[derive(CandidType, AsFixedSizeBytes, StableType, Clone, PartialEq)]
+pub enum AccountStatus{
+    Active,
+    Suspended{ until: u64 },
+    Banned,
+}
+
 #[derive(StableType, AsFixedSizeBytes)]
 pub struct Profile{
     pub first_name: SBox<String>,
@@ -20,10 +29,15 @@ pub struct Profile{
     pub last_name: SBox<String>,
     pub username: SBox<String>,
     pub address: Principal,
+    /// `YYYYMMDD`, like `RegistrationData::date_of_birth`.
     pub date_of_birth: u64,
     pub gender: Gender,
     pub verification_badge: VerificationBadge,
+    pub verification_expires_at: Option<u64>,
+    pub expiry_notified: bool,
     pub number_of_posts: u128,
+    pub status: AccountStatus,
+    pub strikes: u64,
 }
 
 #[derive(CandidType)]
@@ -32,18 +46,17 @@ pub struct ProfileQuery{
     pub middle_name: String,
     pub last_name: String,
     pub username: String,
+    /// `YYYYMMDD`, like `RegistrationData::date_of_birth`.
     pub date_of_birth: u64,
     pub gender: Gender,
     pub verification_badge: VerificationBadge,
+    pub verification_expires_at: Option<u64>,
     pub number_of_posts: u128,
+    pub status: AccountStatus,
+    pub strikes: u64,
 }
 
 impl Profile{
-    pub const MAXIMUM_FIRST_NAME_LENGTH: usize = 15;
-    pub const MAXIMUM_MIDDLE_NAME_LENGTH: usize = 10;
-    pub const MAXIMUM_LAST_NAME_LENGTH: usize = 15;
-    pub const MAXIMUM_USERNAME_LENGTH: usize = 20;
-
     fn new_profile((registration_data, verification_badge, address): (RegistrationData, VerificationBadge, Principal)) -> Option<Profile>{
         let first_name = match SBox::new(registration_data.first_name){
             Ok(first_name) => first_name,
@@ -69,8 +82,12 @@ impl Profile{
             address,
             date_of_birth: registration_data.date_of_birth,
             gender: registration_data.gender,
+            verification_expires_at: _verification_expiry(&verification_badge, ic_cdk::api::time()),
+            expiry_notified: false,
             verification_badge,
             number_of_posts: 0,
+            status: AccountStatus::Active,
+            strikes: 0,
         };
         Some(profile_data)
     }
@@ -84,7 +101,10 @@ impl Profile{
             date_of_birth: self.date_of_birth,
             gender: self.gender.clone(),
             verification_badge: self.verification_badge.clone(),
+            verification_expires_at: self.verification_expires_at,
             number_of_posts: self.number_of_posts,
+            status: self.status.clone(),
+            strikes: self.strikes,
         }
     }
 
@@ -111,25 +131,56 @@ impl Profile{
     fn _change_verification_badge(&mut self, new_verificiation_badge: VerificationBadge){
         self.verification_badge = new_verificiation_badge;
     }
+
+    fn _set_verification_expiry(&mut self, expires_at: Option<u64>){
+        self.verification_expires_at = expires_at;
+        self.expiry_notified = false;
+    }
 }
 
+/// Profiles sorted by address, with `usernames` indexing them by the folded
+/// form of their username.
+#[derive(StableType, AsFixedSizeBytes)]
 pub struct UserProfiles{
-    pub users: SVec<Profile>
+    pub users: SVec<Profile>,
+    pub usernames: SHashMap<SBox<String>, Principal>,
 }
 
 impl Default for UserProfiles{
     fn default() -> Self {
-        Self { users: SVec::new() }
+        Self { users: SVec::new(), usernames: SHashMap::new() }
     }
 }
 
 impl UserProfiles{
-    fn _user_name_taken(&self, user_name: SBox<String>) -> bool{
-        if self.users.binary_search_by(|user| user.username.cmp(&user_name)).is_ok(){
-            true
-        }else{
-            false
+    /// Inserts `profile` keeping `users` sorted by address, which every
+    /// lookup by address relies on. Fails if the address already has one or
+    /// the username folds to one in use.
+    fn register(&mut self, profile: Profile) -> bool{
+        let index = match self.users.binary_search_by(|user| user.address.cmp(&profile.address)){
+            Ok(_) => return false,
+            Err(index) => index
+        };
+        let folded_username = fold_username(&profile.username);
+        if self.usernames.contains_key(&folded_username){
+            return false
         }
+        let indexed = match SBox::new(folded_username.clone()){
+            Ok(key) => self.usernames.insert(key, profile.address).is_ok(),
+            Err(_) => false
+        };
+        if !indexed{
+            return false
+        }
+        if self.users.insert(index, profile).is_err(){
+            self.usernames.remove(&folded_username);
+            return false
+        }
+        true
+    }
+
+    fn _user_name_taken(&self, folded_username: &String) -> bool{
+        self.usernames.contains_key(folded_username)
     }
 
     fn is_this_address_already_registered(&self, address: &Principal) -> bool{
@@ -150,24 +201,149 @@ impl UserProfiles{
             Some(user) => Some(user.username.clone())
         }
     }
+
+    fn get_verification_badge(&self, address: &Principal) -> Option<VerificationBadge>{
+        let index = match self.users.binary_search_by(|user| user.address.cmp(address)){
+            Ok(index) => index,
+            Err(_) => return None
+        };
+        self.users.get(index).map(|user| user.verification_badge.clone())
+    }
+
+    fn get_address(&self, username: &str) -> Option<Principal>{
+        self.usernames.get(&fold_username(username)).map(|address| *address)
+    }
+
+    fn change_verification_badge(&mut self, address: &Principal, new_verification_badge: VerificationBadge, expires_at: Option<u64>) -> Option<VerificationBadge>{
+        let index = match self.users.binary_search_by(|user| user.address.cmp(address)){
+            Ok(index) => index,
+            Err(_) => return None
+        };
+        let mut user = self.users.get_mut(index)?;
+        let old_verification_badge = user.verification_badge.clone();
+        user._change_verification_badge(new_verification_badge);
+        user._set_verification_expiry(expires_at);
+        Some(old_verification_badge)
+    }
+
+    fn verification_expiry(&self, address: &Principal) -> Option<Option<u64>>{
+        let index = match self.users.binary_search_by(|user| user.address.cmp(address)){
+            Ok(index) => index,
+            Err(_) => return None
+        };
+        self.users.get(index).map(|user| user.verification_expires_at)
+    }
+
+    fn set_verification_expiry(&mut self, address: &Principal, expires_at: Option<u64>) -> bool{
+        let index = match self.users.binary_search_by(|user| user.address.cmp(address)){
+            Ok(index) => index,
+            Err(_) => return false
+        };
+        match self.users.get_mut(index){
+            None => false,
+            Some(mut user) => {
+                user._set_verification_expiry(expires_at);
+                true
+            }
+        }
+    }
+
+    fn mark_expiry_notified(&mut self, address: &Principal){
+        if let Ok(index) = self.users.binary_search_by(|user| user.address.cmp(address)){
+            if let Some(mut user) = self.users.get_mut(index){
+                user.expiry_notified = true;
+            }
+        }
+    }
+
+    fn profile(&self, address: &Principal) -> Option<ProfileQuery>{
+        let index = match self.users.binary_search_by(|user| user.address.cmp(address)){
+            Ok(index) => index,
+            Err(_) => return None
+        };
+        self.users.get(index).map(|user| user._to_profile_query())
+    }
+
+    fn account_status(&self, address: &Principal) -> Option<AccountStatus>{
+        let index = match self.users.binary_search_by(|user| user.address.cmp(address)){
+            Ok(index) => index,
+            Err(_) => return None
+        };
+        self.users.get(index).map(|user| user.status.clone())
+    }
+
+    fn set_account_status(&mut self, address: &Principal, status: AccountStatus) -> bool{
+        let index = match self.users.binary_search_by(|user| user.address.cmp(address)){
+            Ok(index) => index,
+            Err(_) => return false
+        };
+        match self.users.get_mut(index){
+            None => false,
+            Some(mut user) => {
+                user.status = status;
+                true
+            }
+        }
+    }
+
+    fn update_profile(&mut self, address: &Principal, first_name: SBox<String>, middle_name: SBox<String>, last_name: SBox<String>) -> bool{
+        let index = match self.users.binary_search_by(|user| user.address.cmp(address)){
+            Ok(index) => index,
+            Err(_) => return false
+        };
+        match self.users.get_mut(index){
+            None => false,
+            Some(mut user) => {
+                user._update_first_name(first_name);
+                user._update_middle_name(middle_name);
+                user._update_last_name(last_name);
+                true
+            }
+        }
+    }
+
+    fn add_strike(&mut self, address: &Principal) -> Option<u64>{
+        let index = match self.users.binary_search_by(|user| user.address.cmp(address)){
+            Ok(index) => index,
+            Err(_) => return None
+        };
+        let mut user = self.users.get_mut(index)?;
+        user.strikes += 1;
+        Some(user.strikes)
+    }
+
+    fn remove_profile(&mut self, address: &Principal) -> Option<Profile>{
+        let index = match self.users.binary_search_by(|user| user.address.cmp(address)){
+            Ok(index) => index,
+            Err(_) => return None
+        };
+        let profile = self.users.remove(index);
+        self.usernames.remove(&fold_username(&profile.username));
+        Some(profile)
+    }
+
+    /// Returns `(address, expires_at, expiry_notified)` for every account
+    /// whose verification has an expiry.
+    fn expiring_verifications(&self) -> Vec<(Principal, u64, bool)>{
+        self.users.iter()
+            .filter_map(|user| user.verification_expires_at.map(|expires_at| (user.address, expires_at, user.expiry_notified)))
+            .collect()
+    }
 }
 
 thread_local! {
     pub static USER_PROFILE: RefCell<UserProfiles> = RefCell::default();
+    /// Folded usernames held by registrations waiting on their fee payment.
+    static USERNAMES_IN_REGISTRATION: RefCell<BTreeSet<String>> = RefCell::default();
 }
 
-fn _register_profile(profile: Profile) -> bool{
-    USER_PROFILE.with(|state|{
-        let state = &mut state.borrow_mut();
-        match state.users.push(profile){
-            Ok(_) => true,
-            Err(_) => false
-        }
-    })
+pub(crate) fn _register_profile(profile: Profile) -> bool{
+    USER_PROFILE.with(|state| state.borrow_mut().register(profile))
 }
 
-fn _username_check(username: SBox<String>) -> bool{
-    USER_PROFILE.with(|state| state.borrow()._user_name_taken(username))
+/// Whether `username`, or a name that folds to the same form, is in use.
+fn _username_check(username: &str) -> bool{
+    USER_PROFILE.with(|state| state.borrow()._user_name_taken(&fold_username(username)))
 }
 
 fn is_this_user_already_registered(address: &Principal) -> bool{
@@ -178,102 +354,404 @@ pub(crate) fn get_username(address: &Principal) -> Option<String>{
     USER_PROFILE.with(|state| state.borrow().get_username(address))
 }
 
+pub(crate) fn get_verification_badge(address: &Principal) -> Option<VerificationBadge>{
+    USER_PROFILE.with(|state| state.borrow().get_verification_badge(address))
+}
+
+pub(crate) fn get_address(username: &str) -> Option<Principal>{
+    USER_PROFILE.with(|state| state.borrow().get_address(username))
+}
+
+pub(crate) fn _change_verification_badge(address: &Principal, new_verification_badge: VerificationBadge, expires_at: Option<u64>) -> Option<VerificationBadge>{
+    USER_PROFILE.with(|state| state.borrow_mut().change_verification_badge(address, new_verification_badge, expires_at))
+}
+
+pub(crate) fn _query_verification_expiry(address: &Principal) -> Option<Option<u64>>{
+    USER_PROFILE.with(|state| state.borrow().verification_expiry(address))
+}
+
+pub(crate) fn _set_verification_expiry(address: &Principal, expires_at: Option<u64>) -> bool{
+    USER_PROFILE.with(|state| state.borrow_mut().set_verification_expiry(address, expires_at))
+}
+
+pub(crate) fn _mark_expiry_notified(address: &Principal){
+    USER_PROFILE.with(|state| state.borrow_mut().mark_expiry_notified(address))
+}
+
+pub(crate) fn _query_profile(address: &Principal) -> Option<ProfileQuery>{
+    USER_PROFILE.with(|state| state.borrow().profile(address))
+}
+
+pub(crate) fn _set_account_status(address: &Principal, status: AccountStatus) -> bool{
+    USER_PROFILE.with(|state| state.borrow_mut().set_account_status(address, status))
+}
+
+/// `Some` when `address` may not write at `now`, holding the end of its
+/// suspension or `None` for a ban.
+pub(crate) fn _suspended_until(address: &Principal, now: u64) -> Option<Option<u64>>{
+    match USER_PROFILE.with(|state| state.borrow().account_status(address)){
+        Some(AccountStatus::Suspended{ until }) if until > now => Some(Some(until)),
+        Some(AccountStatus::Banned) => Some(None),
+        _ => None
+    }
+}
+
+pub(crate) fn _add_strike(address: &Principal) -> Option<u64>{
+    USER_PROFILE.with(|state| state.borrow_mut().add_strike(address))
+}
+
+/// Removes the profile of `address`, which frees its username, and returns
+/// the username and badge it had.
+pub(crate) fn _remove_profile(address: &Principal) -> Option<(String, VerificationBadge)>{
+    let profile = USER_PROFILE.with(|state| state.borrow_mut().remove_profile(address))?;
+    Some((profile.username.clone(), profile.verification_badge.clone()))
+}
+
+pub(crate) fn _expiring_verifications() -> Vec<(Principal, u64, bool)>{
+    USER_PROFILE.with(|state| state.borrow().expiring_verifications())
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct UpdateProfileData{
+    pub first_name: String,
+    pub middle_name: String,
+    pub last_name: String,
+}
+
 #[derive(CandidType, Deserialize)]
 pub struct RegistrationData{
     pub first_name: String,
     pub middle_name: String,
     pub last_name: String,
     pub username: String,
+    /// Calendar date written as the number `YYYYMMDD`, e.g. `19900131`.
+    /// Unlike the timestamps elsewhere in the API this is not nanoseconds
+    /// since the epoch, as a birth date has no time of day or time zone and
+    /// may fall before 1970.
     pub date_of_birth: u64,
     pub gender: Gender,
 }
 
-#[update]
-#[candid_method(update)]
-pub fn register_as_regular_user(registration_data: RegistrationData) -> RegistrationResponse{
-    let caller = ic_cdk::caller();
-    if is_this_user_already_registered(&caller){
-        return RegistrationResponse::AlreadyRegistered
+impl UpdateProfileData{
+    fn normalized(self) -> Self{
+        Self{
+            first_name: normalize_text(self.first_name),
+            middle_name: normalize_text(self.middle_name),
+            last_name: normalize_text(self.last_name),
+        }
     }
-    if let Err(e) = registration_check(&registration_data){
-        return e
+}
+
+impl RegistrationData{
+    fn normalized(self) -> Self{
+        Self{
+            first_name: normalize_text(self.first_name),
+            middle_name: normalize_text(self.middle_name),
+            last_name: normalize_text(self.last_name),
+            ..self
+        }
     }
-    let username = registration_data.username.clone();
-    let boxed_username = match SBox::new(username.clone()){
-        Ok(username) => username,
-        Err(_) => return RegistrationResponse::FailedToAllocateMemory
+}
+
+/// Charges the configured price of `verification_badge` in `payment_token`,
+/// or in ICP when it is `None`; free badges never reach a ledger.
+async fn _collect_verification_fee(caller: Principal, verification_badge: &VerificationBadge, payment_token: Option<Principal>) -> Result<(), RegistrationResponse>{
+    let charge = match _verification_charge_in(verification_badge, &payment_token){
+        None => return Err(RegistrationResponse::UnsupportedPaymentToken),
+        Some(charge) => charge
     };
-    if _username_check(boxed_username){
-        return RegistrationResponse::UserNameTaken
+    match collect_payment(caller, &charge, payment_token).await{
+        Ok(None) => Ok(()),
+        Ok(Some(block_index)) => {
+            if _issue_receipt(caller, ReceiptKind::Registration, block_index, charge.clone(), payment_token, Some(verification_badge.clone())).is_none(){
+                // without a receipt the fee could not be refunded later, so pay it back now
+                let _ = refund_payment(caller, &charge, payment_token).await;
+                return Err(RegistrationResponse::FailedToAllocateMemory)
+            }
+            Ok(())
+        },
+        Err(PaymentError::InsufficientDeposit) => Err(RegistrationResponse::VerificationAmountNotPaid),
+        Err(PaymentError::PaymentInProgress) => Err(RegistrationResponse::PaymentInProgress),
+        Err(_) => Err(RegistrationResponse::PaymentFailed),
     }
-    let profile = match Profile::new_profile((registration_data, VerificationBadge::RegularVerified, caller)){
-        Some(profile) => profile,
-        None => return RegistrationResponse::FailedToAllocateMemory
-    };
-    _register_profile(profile);
-    RegistrationResponse::Success { username }
 }
 
-#[update]
-#[candid_method(update)]
-pub fn register_as_plus_user(registration_data: RegistrationData) -> RegistrationResponse{
-    let caller = ic_cdk::caller();
+/// The one registration pipeline behind `register` and the `register_as_*`
+/// endpoints. It validates the caller and the registration data, then holds
+/// the folded username so no other registration can take it while the fee
+/// is collected. Once the fee is paid and its receipt issued, the hold is
+/// released and the account and username are checked again, as either may
+/// have changed during the await. Last, the profile, post storage and user
+/// counters are created all or nothing. A fee paid for a registration that
+/// fails after the payment keeps its receipt and can be refunded through
+/// `refund_verification`.
+async fn _register(caller: Principal, registration_data: RegistrationData, verification_badge: VerificationBadge, payment_token: Option<Principal>) -> RegistrationResponse{
+    if let Err(retry_after_ns) = _check_rate_limit(caller, "register"){
+        return RegistrationResponse::RateLimited{ retry_after_ns }
+    }
     if is_this_user_already_registered(&caller){
         return RegistrationResponse::AlreadyRegistered
     }
+    let registration_data = registration_data.normalized();
     if let Err(e) = registration_check(&registration_data){
         return e
     }
     let username = registration_data.username.clone();
-    let boxed_username = match SBox::new(username.clone()){
-        Ok(username) => username,
-        Err(_) => return RegistrationResponse::FailedToAllocateMemory
-    };
-    if _username_check(boxed_username){
+    if _username_check(&username){
+        return RegistrationResponse::UserNameTaken
+    }
+    let folded_username = fold_username(&username);
+    let reserved = USERNAMES_IN_REGISTRATION.with(|state| state.borrow_mut().insert(folded_username.clone()));
+    if !reserved{
+        return RegistrationResponse::UserNameTaken
+    }
+    let payment = _collect_verification_fee(caller, &verification_badge, payment_token).await;
+    USERNAMES_IN_REGISTRATION.with(|state| state.borrow_mut().remove(&folded_username));
+    if let Err(e) = payment{
+        return e
+    }
+    if is_this_user_already_registered(&caller){
+        return RegistrationResponse::AlreadyRegistered
+    }
+    if _username_check(&username){
         return RegistrationResponse::UserNameTaken
     }
-    let profile = match Profile::new_profile((registration_data, VerificationBadge::PlusVerified, caller)){
+    let storage_key = match SBox::new(username.clone()){
+        Ok(storage_key) => storage_key,
+        Err(_) => return RegistrationResponse::FailedToAllocateMemory
+    };
+    let profile = match Profile::new_profile((registration_data, verification_badge.clone(), caller)){
         Some(profile) => profile,
         None => return RegistrationResponse::FailedToAllocateMemory
     };
-    _register_profile(profile);
+    // nothing below awaits, so no other call sees a half-registered account
+    if !_register_profile(profile){
+        return RegistrationResponse::FailedToAllocateMemory
+    }
+    if !_allocate_storage(storage_key){
+        _remove_profile(&caller);
+        return RegistrationResponse::FailedToAllocateMemory
+    }
+    _add_user(&verification_badge);
     RegistrationResponse::Success { username }
 }
 
 #[update]
 #[candid_method(update)]
-pub fn register_as_business_account(registration_data: RegistrationData) -> RegistrationResponse{
+pub async fn register(registration_data: RegistrationData, verification_badge: VerificationBadge, payment_token: Option<Principal>) -> RegistrationResponse{
     let caller = ic_cdk::caller();
-    if is_this_user_already_registered(&caller){
-        return RegistrationResponse::AlreadyRegistered
+    _register(caller, registration_data, verification_badge, payment_token).await
+}
+
+/// Same as `register` with `VerificationBadge::RegularVerified`.
+#[update]
+#[candid_method(update)]
+pub async fn register_as_regular_user(registration_data: RegistrationData, payment_token: Option<Principal>) -> RegistrationResponse{
+    let caller = ic_cdk::caller();
+    _register(caller, registration_data, VerificationBadge::RegularVerified, payment_token).await
+}
+
+/// Same as `register` with `VerificationBadge::PlusVerified`.
+#[update]
+#[candid_method(update)]
+pub async fn register_as_plus_user(registration_data: RegistrationData, payment_token: Option<Principal>) -> RegistrationResponse{
+    let caller = ic_cdk::caller();
+    _register(caller, registration_data, VerificationBadge::PlusVerified, payment_token).await
+}
+
+/// Same as `register` with `VerificationBadge::BusinessVerified`.
+#[update]
+#[candid_method(update)]
+pub async fn register_as_business_account(registration_data: RegistrationData, payment_token: Option<Principal>) -> RegistrationResponse{
+    let caller = ic_cdk::caller();
+    _register(caller, registration_data, VerificationBadge::BusinessVerified, payment_token).await
+}
+
+#[update]
+#[candid_method(update)]
+pub fn update_profile(update_profile_data: UpdateProfileData) -> UpdateProfileResponse{
+    let caller = ic_cdk::caller();
+    if let Err(retry_after_ns) = _check_rate_limit(caller, "update_profile"){
+        return UpdateProfileResponse::RateLimited{ retry_after_ns }
     }
-    if let Err(e) = registration_check(&registration_data){
-        return e
+    if !is_this_user_already_registered(&caller){
+        return UpdateProfileResponse::UserNotRegistered
     }
-    let username = registration_data.username.clone();
-    let boxed_username = match SBox::new(username.clone()){
-        Ok(username) => username,
-        Err(_) => return RegistrationResponse::FailedToAllocateMemory
+    if let Some(until) = _suspended_until(&caller, ic_cdk::api::time()){
+        return UpdateProfileResponse::Suspended{ until }
+    }
+    let update_profile_data = update_profile_data.normalized();
+    let limits = _profile_limits();
+    if text_length(&update_profile_data.first_name) > limits.max_first_name_length{
+        return UpdateProfileResponse::FirstNameTooLong
+    }else if text_length(&update_profile_data.middle_name) > limits.max_middle_name_length{
+        return UpdateProfileResponse::MiddleNameTooLong
+    }else if text_length(&update_profile_data.last_name) > limits.max_last_name_length{
+        return UpdateProfileResponse::LastNameTooLong
+    }
+    let (first_name, middle_name, last_name) = match (SBox::new(update_profile_data.first_name), SBox::new(update_profile_data.middle_name), SBox::new(update_profile_data.last_name)){
+        (Ok(first_name), Ok(middle_name), Ok(last_name)) => (first_name, middle_name, last_name),
+        _ => return UpdateProfileResponse::FailedToAllocateMemory
     };
-    if _username_check(boxed_username){
-        return RegistrationResponse::UserNameTaken
+    if !USER_PROFILE.with(|state| state.borrow_mut().update_profile(&caller, first_name, middle_name, last_name)){
+        return UpdateProfileResponse::UserNotRegistered
     }
-    let profile = match Profile::new_profile((registration_data, VerificationBadge::BusinessVerified, caller)){
-        Some(profile) => profile,
-        None => return RegistrationResponse::FailedToAllocateMemory
+    UpdateProfileResponse::Success
+}
+
+/// Nobody is older than this; an earlier date of birth is a typo.
+const MAXIMUM_AGE: u64 = 130;
+
+/// `date_of_birth` as `(year, month, day)`, if it is a real date written as
+/// `YYYYMMDD`. Years have at most four digits, which keeps every nanosecond
+/// timestamp after the first tenth of a second of 1970 out.
+fn calendar_date(date_of_birth: u64) -> Option<(u64, u64, u64)>{
+    if date_of_birth >= 100_000_000{
+        return None
+    }
+    let (year, month, day) = (date_of_birth / 10_000, date_of_birth / 100 % 100, date_of_birth % 100);
+    if !(1..=12).contains(&month) || day == 0 || day > days_in_month(year, month){
+        return None
+    }
+    Some((year, month, day))
+}
+
+/// Checks that `date_of_birth` is a real `YYYYMMDD` date, not after `today`
+/// and at least `minimum_age` full years before it.
+fn date_of_birth_check(date_of_birth: u64, today: (u64, u64, u64), minimum_age: u64) -> Result<(), RegistrationResponse>{
+    let (year, month, day) = match calendar_date(date_of_birth){
+        None => return Err(RegistrationResponse::InvalidDateOfBirth),
+        Some(date) => date
     };
-    _register_profile(profile);
-    RegistrationResponse::Success { username }
+    let (this_year, this_month, this_day) = today;
+    if (year, month, day) > today || year + MAXIMUM_AGE < this_year{
+        return Err(RegistrationResponse::InvalidDateOfBirth)
+    }
+    let had_birthday = (this_month, this_day) >= (month, day);
+    let age = this_year - year - if had_birthday{ 0 }else{ 1 };
+    if age < minimum_age{
+        return Err(RegistrationResponse::TooYoung)
+    }
+    Ok(())
+}
+
+/// The `YYYYMMDD` form of a date of birth stored before the field had a
+/// defined format. Those were stored unchecked; a value that is not already
+/// a `YYYYMMDD` date but reads as a nanosecond timestamp from 1970-01-02 on,
+/// the unit of every other time in the API, becomes the date it falls on.
+/// Anything else is kept as it is.
+fn migrated_date_of_birth(date_of_birth: u64) -> u64{
+    if calendar_date(date_of_birth).is_some() || date_of_birth < NANOSECONDS_PER_DAY{
+        return date_of_birth
+    }
+    let (year, month, day) = date_of(date_of_birth);
+    year * 10_000 + month * 100 + day
+}
+
+/// Rewrites every stored date of birth into `YYYYMMDD`. Runs after each
+/// upgrade; converted values are left alone on later runs.
+pub(crate) fn _migrate_dates_of_birth(){
+    USER_PROFILE.with(|state|{
+        let state = &mut state.borrow_mut();
+        for index in 0..state.users.len(){
+            if let Some(mut user) = state.users.get_mut(index){
+                user.date_of_birth = migrated_date_of_birth(user.date_of_birth);
+            }
+        }
+    })
 }
 
 fn registration_check(registration_data: &RegistrationData) -> Result<(), RegistrationResponse>{
-    if registration_data.first_name.len() > Profile::MAXIMUM_FIRST_NAME_LENGTH{
+    username_check(&registration_data.username)?;
+    date_of_birth_check(registration_data.date_of_birth, date_of(ic_cdk::api::time()), _minimum_age())?;
+    let limits = _profile_limits();
+    if text_length(&registration_data.first_name) > limits.max_first_name_length{
         Err(RegistrationResponse::FirstNameTooLong)
-    }else if registration_data.middle_name.len() > Profile::MAXIMUM_MIDDLE_NAME_LENGTH{
+    }else if text_length(&registration_data.middle_name) > limits.max_middle_name_length{
         Err(RegistrationResponse::MiddleNameTooLong)
-    }else if registration_data.last_name.len() > Profile::MAXIMUM_LAST_NAME_LENGTH{
+    }else if text_length(&registration_data.last_name) > limits.max_last_name_length{
         Err(RegistrationResponse::LastNameTooLong)
     }else{
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{principal, profile, init_memory};
+
+    const TODAY: (u64, u64, u64) = (2024, 3, 15);
+
+    fn rejection(date_of_birth: u64) -> Option<RegistrationResponse>{
+        date_of_birth_check(date_of_birth, TODAY, 13).err()
+    }
+
+    #[test]
+    fn timestamps_convert_to_utc_dates() {
+        assert_eq!(date_of(0), (1970, 1, 1));
+        assert_eq!(date_of(951_782_400 * 1_000_000_000), (2000, 2, 29));
+        assert_eq!(date_of(1_710_460_800 * 1_000_000_000 + 1), (2024, 3, 15));
+    }
+
+    #[test]
+    fn age_is_counted_in_full_years() {
+        assert!(date_of_birth_check(20110315, TODAY, 13).is_ok());
+        assert!(matches!(rejection(20110316), Some(RegistrationResponse::TooYoung)));
+        assert!(date_of_birth_check(20080229, TODAY, 13).is_ok());
+        assert!(date_of_birth_check(19600101, TODAY, 13).is_ok());
+    }
+
+    #[test]
+    fn impossible_dates_of_birth_are_rejected() {
+        assert!(matches!(rejection(20110230), Some(RegistrationResponse::InvalidDateOfBirth)));
+        assert!(matches!(rejection(20111301), Some(RegistrationResponse::InvalidDateOfBirth)));
+        assert!(matches!(rejection(20240316), Some(RegistrationResponse::InvalidDateOfBirth)));
+        assert!(matches!(rejection(18500101), Some(RegistrationResponse::InvalidDateOfBirth)));
+        assert!(matches!(rejection(1_700_000_000_000), Some(RegistrationResponse::InvalidDateOfBirth)));
+    }
+
+    #[test]
+    fn profiles_stay_sorted_by_address() {
+        init_memory();
+        let mut profiles = UserProfiles::default();
+        for id in [5, 2, 9] {
+            assert!(profiles.register(profile(id)));
+        }
+        assert!(!profiles.register(profile(2)));
+        for id in [2, 5, 9] {
+            assert_eq!(profiles.get_username(&principal(id)), Some(format!("user_{id}")));
+        }
+        assert!(profiles.remove_profile(&principal(5)).is_some());
+        assert!(!profiles.is_this_address_already_registered(&principal(5)));
+        assert!(profiles.is_this_address_already_registered(&principal(9)));
+    }
+
+    #[test]
+    fn addresses_are_found_through_the_folded_username() {
+        init_memory();
+        let mut profiles = UserProfiles::default();
+        assert!(profiles.register(Profile{ username: SBox::new("Lola".to_string()).unwrap(), ..profile(2) }));
+        assert_eq!(profiles.get_address("lola"), Some(principal(2)));
+        assert_eq!(profiles.get_address("L0LA"), Some(principal(2)));
+        assert!(profiles._user_name_taken(&fold_username("l01a")));
+        assert!(!profiles.register(Profile{ username: SBox::new("lo1a".to_string()).unwrap(), ..profile(3) }));
+        assert!(profiles.remove_profile(&principal(2)).is_some());
+        assert_eq!(profiles.get_address("lola"), None);
+        assert!(profiles.register(Profile{ username: SBox::new("lo1a".to_string()).unwrap(), ..profile(3) }));
+    }
+
+    #[test]
+    fn dates_of_birth_stored_as_timestamps_are_migrated() {
+        init_memory();
+        assert_eq!(migrated_date_of_birth(19900131), 19900131);
+        assert_eq!(migrated_date_of_birth(951_782_400 * 1_000_000_000), 20000229);
+        assert_eq!(migrated_date_of_birth(42), 42);
+        assert!(_register_profile(Profile{ date_of_birth: 951_782_400 * 1_000_000_000, ..profile(2) }));
+        assert!(_register_profile(profile(3)));
+        _migrate_dates_of_birth();
+        _migrate_dates_of_birth();
+        let dates: Vec<u64> = USER_PROFILE.with(|state| state.borrow().users.iter().map(|user| user.date_of_birth).collect());
+        assert_eq!(dates, vec![20000229, 19900101]);
+    }
+}
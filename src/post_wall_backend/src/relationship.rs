@@ -0,0 +1,209 @@
+use std::cell::RefCell;
+
+use candid::{candid_method, Principal};
+use ic_cdk_macros::*;
+use ic_stable_memory::{derive::{AsFixedSizeBytes, StableType}, collections::{SHashMap, SVec}};
+
+use crate::{registration::{get_username, get_address}, response::RelationshipResponse, rate_limit::_check_rate_limit};
+
+/// Per-user lists of other principals, kept in insertion order.
+#[derive(StableType, AsFixedSizeBytes)]
+pub struct RelationshipList{
+    pub lists: SHashMap<Principal, SVec<Principal>>,
+}
+
+impl Default for RelationshipList{
+    fn default() -> Self {
+        Self{
+            lists: SHashMap::new(),
+        }
+    }
+}
+
+impl RelationshipList{
+    fn contains(&self, owner: &Principal, other: &Principal) -> bool{
+        match self.lists.get(owner){
+            None => false,
+            Some(list) => list.iter().any(|entry| *entry == *other)
+        }
+    }
+
+    pub(crate) fn add(&mut self, owner: Principal, other: Principal) -> bool{
+        if self.contains(&owner, &other){
+            return true
+        }
+        if !self.lists.contains_key(&owner) && self.lists.insert(owner, SVec::new()).is_err(){
+            return false
+        }
+        match self.lists.get_mut(&owner){
+            None => false,
+            Some(mut list) => list.push(other).is_ok()
+        }
+    }
+
+    pub(crate) fn remove(&mut self, owner: &Principal, other: &Principal){
+        if let Some(mut list) = self.lists.get_mut(owner){
+            if let Some(index) = list.iter().position(|entry| *entry == *other){
+                list.remove(index);
+            }
+        }
+    }
+
+    fn page(&self, owner: &Principal, start: usize, limit: usize) -> Vec<Principal>{
+        match self.lists.get(owner){
+            None => Vec::new(),
+            Some(list) => list.iter().skip(start).take(limit).map(|entry| *entry).collect()
+        }
+    }
+}
+
+#[derive(StableType, AsFixedSizeBytes, Default)]
+pub struct RelationshipState{
+    pub blocked: RelationshipList,
+    pub muted: RelationshipList,
+}
+
+thread_local! {
+    pub static RELATIONSHIP_STATE: RefCell<RelationshipState> = RefCell::default();
+}
+
+/// Whether `blocker` has blocked `address`.
+pub(crate) fn _has_blocked(blocker: &Principal, address: &Principal) -> bool{
+    RELATIONSHIP_STATE.with(|state| state.borrow().blocked.contains(blocker, address))
+}
+
+/// Whether `muter` has muted `address`.
+pub(crate) fn _has_muted(muter: &Principal, address: &Principal) -> bool{
+    RELATIONSHIP_STATE.with(|state| state.borrow().muted.contains(muter, address))
+}
+
+pub(crate) fn _remove_relationships_of(address: &Principal){
+    RELATIONSHIP_STATE.with(|state|{
+        let state = &mut state.borrow_mut();
+        state.blocked.lists.remove(address);
+        state.muted.lists.remove(address);
+    })
+}
+
+fn _target_of(caller: &Principal, username: &str) -> Result<Principal, RelationshipResponse>{
+    if get_username(caller).is_none(){
+        return Err(RelationshipResponse::UserNotRegistered)
+    }
+    let address = match get_address(username){
+        None => return Err(RelationshipResponse::UserNotFound),
+        Some(address) => address
+    };
+    if address == *caller{
+        return Err(RelationshipResponse::CannotTargetYourself)
+    }
+    Ok(address)
+}
+
+fn _usernames(addresses: Vec<Principal>) -> Vec<String>{
+    addresses.iter().filter_map(get_username).collect()
+}
+
+/// Blocks `username` from mentioning the caller, from seeing the caller's
+/// posts through `get_posts_by_user` and from tipping them.
+#[update]
+#[candid_method(update)]
+pub fn block_user(username: String) -> RelationshipResponse{
+    let caller = ic_cdk::caller();
+    if let Err(retry_after_ns) = _check_rate_limit(caller, "block_user"){
+        return RelationshipResponse::RateLimited{ retry_after_ns }
+    }
+    let address = match _target_of(&caller, &username){
+        Err(e) => return e,
+        Ok(address) => address
+    };
+    if !RELATIONSHIP_STATE.with(|state| state.borrow_mut().blocked.add(caller, address)){
+        return RelationshipResponse::FailedToAllocateMemory
+    }
+    RelationshipResponse::Success
+}
+
+#[update]
+#[candid_method(update)]
+pub fn unblock_user(username: String) -> RelationshipResponse{
+    let caller = ic_cdk::caller();
+    if let Err(retry_after_ns) = _check_rate_limit(caller, "unblock_user"){
+        return RelationshipResponse::RateLimited{ retry_after_ns }
+    }
+    let address = match _target_of(&caller, &username){
+        Err(e) => return e,
+        Ok(address) => address
+    };
+    RELATIONSHIP_STATE.with(|state| state.borrow_mut().blocked.remove(&caller, &address));
+    RelationshipResponse::Success
+}
+
+#[update]
+#[candid_method(update)]
+pub fn mute_user(username: String) -> RelationshipResponse{
+    let caller = ic_cdk::caller();
+    if let Err(retry_after_ns) = _check_rate_limit(caller, "mute_user"){
+        return RelationshipResponse::RateLimited{ retry_after_ns }
+    }
+    let address = match _target_of(&caller, &username){
+        Err(e) => return e,
+        Ok(address) => address
+    };
+    if !RELATIONSHIP_STATE.with(|state| state.borrow_mut().muted.add(caller, address)){
+        return RelationshipResponse::FailedToAllocateMemory
+    }
+    RelationshipResponse::Success
+}
+
+#[update]
+#[candid_method(update)]
+pub fn unmute_user(username: String) -> RelationshipResponse{
+    let caller = ic_cdk::caller();
+    if let Err(retry_after_ns) = _check_rate_limit(caller, "unmute_user"){
+        return RelationshipResponse::RateLimited{ retry_after_ns }
+    }
+    let address = match _target_of(&caller, &username){
+        Err(e) => return e,
+        Ok(address) => address
+    };
+    RELATIONSHIP_STATE.with(|state| state.borrow_mut().muted.remove(&caller, &address));
+    RelationshipResponse::Success
+}
+
+/// Usernames the caller has blocked, oldest first. Accounts that have since
+/// been deleted are skipped, so a page can be shorter than `limit`.
+#[query]
+#[candid_method(query)]
+pub fn get_blocked(start: u64, limit: u64) -> Vec<String>{
+    let caller = ic_cdk::caller();
+    _usernames(RELATIONSHIP_STATE.with(|state| state.borrow().blocked.page(&caller, start as usize, limit as usize)))
+}
+
+/// Usernames the caller has muted, paged like `get_blocked`.
+#[query]
+#[candid_method(query)]
+pub fn get_muted(start: u64, limit: u64) -> Vec<String>{
+    let caller = ic_cdk::caller();
+    _usernames(RELATIONSHIP_STATE.with(|state| state.borrow().muted.page(&caller, start as usize, limit as usize)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{principal, init_memory};
+
+    #[test]
+    fn lists_are_kept_per_owner_without_duplicates() {
+        init_memory();
+        let mut list = RelationshipList::default();
+        assert!(list.add(principal(2), principal(3)));
+        assert!(list.add(principal(2), principal(3)));
+        assert!(list.add(principal(2), principal(4)));
+        assert!(list.add(principal(5), principal(2)));
+        assert!(list.contains(&principal(2), &principal(3)));
+        assert!(!list.contains(&principal(3), &principal(2)));
+        assert_eq!(list.page(&principal(2), 0, 10), vec![principal(3), principal(4)]);
+        assert_eq!(list.page(&principal(2), 1, 10), vec![principal(4)]);
+        list.remove(&principal(2), &principal(3));
+        assert_eq!(list.page(&principal(2), 0, 10), vec![principal(4)]);
+    }
+}
\ No newline at end of file
@@ -1,4 +1,7 @@
-use candid::CandidType;
+use candid::{CandidType, Nat};
+use ic_ledger_types::Tokens;
+
+use crate::{verification_status::BadgeChange, treasury::WithdrawalQuery, receipt::Receipt, account::UserDataExport, moderation::Report, content_filter::ContentRejection};
 
 #[derive(CandidType)]
 pub enum UpdateStateResponse{
@@ -10,19 +13,317 @@ pub enum UpdateStateResponse{
 pub enum RegistrationResponse{
     Success{ username: String },
     UserNameTaken,
+    UserNameEmpty,
+    UserNameTooLong,
+    UserNameInvalidCharacters,
+    UserNameStartsWithDigit,
+    UserNameReserved,
     AlreadyRegistered,
     VerificationAmountNotPaid,
+    UnsupportedPaymentToken,
+    PaymentInProgress,
+    PaymentFailed,
+    /// Measured as described on `ProfileLimits`.
+    FirstNameTooLong,
+    MiddleNameTooLong,
+    LastNameTooLong,
+    InvalidDateOfBirth,
+    TooYoung,
+    FailedToAllocateMemory,
+    RateLimited{ retry_after_ns: u64 },
+}
+
+#[derive(CandidType)]
+pub enum UpdateProfileResponse{
+    Success,
+    UserNotRegistered,
+    Suspended{ until: Option<u64> },
+    /// Measured as described on `ProfileLimits`.
     FirstNameTooLong,
     MiddleNameTooLong,
     LastNameTooLong,
     FailedToAllocateMemory,
+    RateLimited{ retry_after_ns: u64 },
+}
+
+#[derive(CandidType)]
+pub enum SuspendUserResponse{
+    Success,
+    Unauthorized,
+    UserNotFound,
 }
 
 #[derive(CandidType)]
 pub enum CreatePostResponse{
     Success,
     UserNotRegistered,
+    /// Measured as described on `ContentLimits`.
     TopicTooLong,
     MessageTooLong,
+    LinksNotAllowed,
+    DailyPostLimitReached,
+    ContentRejected{ reason: ContentRejection },
+    MentionBlocked{ username: String },
+    Suspended{ until: Option<u64> },
+    NotAuthorized,
+    FailedToAllocateMemory,
+    RateLimited{ retry_after_ns: u64 },
+}
+
+#[derive(CandidType)]
+pub enum EditPostResponse{
+    Success,
+    UserNotRegistered,
+    PostNotFound,
+    NotPostAuthor,
+    EditWindowClosed,
+    MentionBlocked{ username: String },
+    Suspended{ until: Option<u64> },
+    NotAuthorized,
+    /// Measured as described on `ContentLimits`.
+    TopicTooLong,
+    MessageTooLong,
+    LinksNotAllowed,
+    ContentRejected{ reason: ContentRejection },
+    FailedToAllocateMemory,
+    RateLimited{ retry_after_ns: u64 },
+}
+
+#[derive(CandidType)]
+pub enum UpgradeBadgeResponse{
+    Success,
+    UserNotRegistered,
+    AlreadyHasBadge,
+    Suspended{ until: Option<u64> },
+    RateLimited{ retry_after_ns: u64 },
+    VerificationAmountNotPaid,
+    UnsupportedPaymentToken,
+    PaymentInProgress,
+    PaymentFailed,
+    PriceChanged{ refunded: bool },
+    FailedToAllocateMemory,
+}
+
+#[derive(CandidType)]
+pub enum SetUserBadgeResponse{
+    Success,
+    Unauthorized,
+    UserNotFound,
     FailedToAllocateMemory,
-}
\ No newline at end of file
+}
+
+#[derive(CandidType)]
+pub enum BadgeAuditLogResponse{
+    Success(Vec<BadgeChange>),
+    Unauthorized,
+}
+
+#[derive(CandidType)]
+pub enum RenewVerificationResponse{
+    Success{ expires_at: u64 },
+    UserNotRegistered,
+    NothingToRenew,
+    Suspended{ until: Option<u64> },
+    VerificationAmountNotPaid,
+    UnsupportedPaymentToken,
+    PaymentInProgress,
+    PaymentFailed,
+}
+
+#[derive(CandidType)]
+pub enum TreasuryBalanceResponse{
+    Success(Nat),
+    Unauthorized,
+    LedgerError(String),
+}
+
+#[derive(CandidType)]
+pub enum WithdrawResponse{
+    Success{ block_index: u64 },
+    Unauthorized,
+    WithdrawalInProgress,
+    MemoAlreadyUsed,
+    InsufficientFunds{ balance: Nat },
+    TransferFailed(String),
+    LedgerError(String),
+}
+
+#[derive(CandidType)]
+pub enum WithdrawalHistoryResponse{
+    Success(Vec<WithdrawalQuery>),
+    Unauthorized,
+}
+
+#[derive(CandidType)]
+pub enum TipPostResponse{
+    Success{ block_index: u64 },
+    PostNotFound,
+    CannotTipOwnPost,
+    Suspended{ until: Option<u64> },
+    InvalidAmount,
+    UnsupportedPaymentToken,
+    InsufficientFunds,
+    PaymentInProgress,
+    PaymentFailed,
+    RateLimited{ retry_after_ns: u64 },
+}
+
+#[derive(CandidType)]
+pub enum DeleteAccountResponse{
+    Success,
+    UserNotRegistered,
+    PaymentInProgress,
+    Suspended{ until: Option<u64> },
+}
+
+#[derive(CandidType)]
+pub enum ExportDataResponse{
+    Success(Box<UserDataExport>),
+    UserNotRegistered,
+}
+
+#[derive(CandidType)]
+pub enum ReportResponse{
+    Success{ report_id: u64 },
+    UserNotRegistered,
+    Suspended{ until: Option<u64> },
+    PostNotFound,
+    UserNotFound,
+    CannotReportYourself,
+    AlreadyReported,
+    FailedToAllocateMemory,
+    RateLimited{ retry_after_ns: u64 },
+}
+
+#[derive(CandidType)]
+pub enum ReportsResponse{
+    Success(Vec<Report>),
+    Unauthorized,
+}
+
+#[derive(CandidType)]
+pub enum ResolveReportResponse{
+    Success,
+    Unauthorized,
+    ReportNotFound,
+    AlreadyResolved,
+    ActionNotApplicable,
+    TargetNotFound,
+}
+
+#[derive(CandidType)]
+pub enum RelationshipResponse{
+    Success,
+    UserNotRegistered,
+    UserNotFound,
+    CannotTargetYourself,
+    FailedToAllocateMemory,
+    RateLimited{ retry_after_ns: u64 },
+}
+
+#[derive(CandidType)]
+pub enum BlockedWordsResponse{
+    Success(Vec<String>),
+    Unauthorized,
+}
+
+#[derive(CandidType)]
+pub enum ReceiptsResponse{
+    Success(Vec<Receipt>),
+    Unauthorized,
+}
+
+#[derive(CandidType)]
+pub enum ReclaimDepositResponse{
+    Success{ block_index: u64, amount: Tokens },
+    NothingToReclaim,
+    PaymentInProgress,
+    TransferFailed(String),
+    LedgerError(String),
+}
+
+#[derive(CandidType)]
+pub enum RefundVerificationResponse{
+    Success{ block_index: u64, amount: Tokens },
+    Unauthorized,
+    AccountStillRegistered,
+    NothingToRefund,
+    InsufficientFunds{ balance: Tokens },
+    TransferFailed(String),
+    LedgerError(String),
+}
+
+#[derive(CandidType)]
+pub enum BusinessProfileResponse{
+    Success,
+    UserNotRegistered,
+    NotBusinessAccount,
+    Suspended{ until: Option<u64> },
+    /// Measured as described on `ProfileLimits`.
+    OrganizationNameTooLong,
+    RegistrationNumberTooLong,
+    WebsiteTooLong,
+    InvalidWebsite,
+    FailedToAllocateMemory,
+    RateLimited{ retry_after_ns: u64 },
+}
+
+#[derive(CandidType)]
+pub enum BusinessMemberResponse{
+    Success,
+    UserNotRegistered,
+    NotBusinessAccount,
+    Suspended{ until: Option<u64> },
+    UserNotFound,
+    CannotTargetYourself,
+    FailedToAllocateMemory,
+    RateLimited{ retry_after_ns: u64 },
+}
+
+#[derive(CandidType)]
+pub enum DelegationResponse{
+    Success,
+    UserNotRegistered,
+    Suspended{ until: Option<u64> },
+    InvalidDelegate,
+    AlreadyExpired,
+    FailedToAllocateMemory,
+    RateLimited{ retry_after_ns: u64 },
+}
+
+#[derive(CandidType)]
+pub enum SchedulePostResponse{
+    Success{ scheduled_post_id: u64 },
+    UserNotRegistered,
+    Suspended{ until: Option<u64> },
+    SchedulingNotAllowed,
+    PublishTimeInPast,
+    PublishTimeTooFar,
+    TooManyScheduledPosts,
+    /// Measured as described on `ContentLimits`.
+    TopicTooLong,
+    MessageTooLong,
+    LinksNotAllowed,
+    ContentRejected{ reason: ContentRejection },
+    MentionBlocked{ username: String },
+    FailedToAllocateMemory,
+    RateLimited{ retry_after_ns: u64 },
+}
+
+#[derive(CandidType)]
+pub enum CancelScheduledPostResponse{
+    Success,
+    ScheduledPostNotFound,
+}
+
+#[derive(CandidType)]
+pub enum PinPostResponse{
+    Success,
+    UserNotRegistered,
+    Suspended{ until: Option<u64> },
+    PostNotFound,
+    NotPostAuthor,
+    PinningNotAllowed,
+    PinLimitReached,
+    RateLimited{ retry_after_ns: u64 },
+}
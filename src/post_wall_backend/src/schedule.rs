@@ -0,0 +1,273 @@
+use std::{cell::RefCell, cmp::Ordering, collections::BTreeMap, time::Duration};
+
+use candid::{CandidType, candid_method, Principal};
+use ic_cdk_macros::*;
+use ic_cdk_timers::TimerId;
+use ic_stable_memory::{SBox, derive::{AsFixedSizeBytes, StableType}, collections::SVec};
+
+use crate::{registration::{get_verification_badge, _suspended_until}, post::{CreatePostData, ContentViolation, _content_check, _create_post_for}, config::{_badge_capabilities, _max_scheduled_posts, _max_schedule_ahead}, rate_limit::_check_rate_limit, notification::{_notify, NotificationKind}, response::{SchedulePostResponse, CancelScheduledPostResponse, CreatePostResponse}};
+
+#[derive(StableType, AsFixedSizeBytes)]
+pub struct ScheduledPost{
+    pub id: u64,
+    pub author: Principal,
+    pub topic: SBox<String>,
+    pub message: SBox<String>,
+    pub publish_at: u64,
+}
+
+#[derive(CandidType)]
+pub struct ScheduledPostQuery{
+    pub id: u64,
+    pub topic: String,
+    pub message: String,
+    pub publish_at: u64,
+}
+
+impl ScheduledPost{
+    fn _to_scheduled_post_query(&self) -> ScheduledPostQuery{
+        ScheduledPostQuery{
+            id: self.id,
+            topic: self.topic.clone(),
+            message: self.message.clone(),
+            publish_at: self.publish_at,
+        }
+    }
+}
+
+/// Posts waiting to be published, ordered by `publish_at`.
+#[derive(StableType, AsFixedSizeBytes)]
+pub struct ScheduleState{
+    pub scheduled: SVec<ScheduledPost>,
+    pub next_id: u64,
+}
+
+impl Default for ScheduleState{
+    fn default() -> Self {
+        Self{
+            scheduled: SVec::new(),
+            next_id: 0,
+        }
+    }
+}
+
+impl ScheduleState{
+    fn schedule(&mut self, author: Principal, create_post_data: CreatePostData, publish_at: u64) -> Option<u64>{
+        let topic = SBox::new(create_post_data.topic).ok()?;
+        let message = SBox::new(create_post_data.message).ok()?;
+        let id = self.next_id;
+        // posts due at the same time keep the order they were scheduled in
+        let index = match self.scheduled.binary_search_by(|post| post.publish_at.cmp(&publish_at).then(Ordering::Less)){
+            Ok(index) | Err(index) => index
+        };
+        self.scheduled.insert(index, ScheduledPost{ id, author, topic, message, publish_at }).ok()?;
+        self.next_id += 1;
+        Some(id)
+    }
+
+    fn cancel(&mut self, author: &Principal, id: u64) -> bool{
+        match self.scheduled.iter().position(|post| post.id == id && post.author == *author){
+            None => false,
+            Some(index) => {
+                self.scheduled.remove(index);
+                true
+            }
+        }
+    }
+
+    fn scheduled_of(&self, author: &Principal) -> Vec<ScheduledPostQuery>{
+        self.scheduled.iter().filter(|post| post.author == *author).map(|post| post._to_scheduled_post_query()).collect()
+    }
+
+    fn count_of(&self, author: &Principal) -> usize{
+        self.scheduled.iter().filter(|post| post.author == *author).count()
+    }
+
+    /// Removes the post with `id` and returns its author and content.
+    fn take(&mut self, id: u64) -> Option<(Principal, CreatePostData)>{
+        let index = self.scheduled.iter().position(|post| post.id == id)?;
+        let post = self.scheduled.remove(index);
+        Some((post.author, CreatePostData{ topic: post.topic.clone(), message: post.message.clone() }))
+    }
+
+    fn publish_times(&self) -> Vec<(u64, u64)>{
+        self.scheduled.iter().map(|post| (post.id, post.publish_at)).collect()
+    }
+
+    /// Removes every post of `author` and returns their ids.
+    fn remove_scheduled_of(&mut self, author: &Principal) -> Vec<u64>{
+        let mut removed = Vec::new();
+        while let Some(index) = self.scheduled.iter().position(|post| post.author == *author){
+            removed.push(self.scheduled.remove(index).id);
+        }
+        removed
+    }
+}
+
+thread_local! {
+    pub static SCHEDULE_STATE: RefCell<ScheduleState> = RefCell::default();
+    /// The timer that publishes each scheduled post, by scheduled post id.
+    static SCHEDULE_TIMERS: RefCell<BTreeMap<u64, TimerId>> = RefCell::default();
+}
+
+/// Sets the timer that publishes scheduled post `id` at `publish_at`, or
+/// right away when that has already passed.
+fn _arm_timer(id: u64, publish_at: u64, now: u64){
+    let delay = Duration::from_nanos(publish_at.saturating_sub(now));
+    let timer = ic_cdk_timers::set_timer(delay, move || _publish_scheduled_post(id));
+    SCHEDULE_TIMERS.with(|timers| timers.borrow_mut().insert(id, timer));
+}
+
+fn _clear_timer(id: u64){
+    if let Some(timer) = SCHEDULE_TIMERS.with(|timers| timers.borrow_mut().remove(&id)){
+        ic_cdk_timers::clear_timer(timer);
+    }
+}
+
+/// Sets a timer for every scheduled post, as timers do not survive an upgrade.
+pub(crate) fn _arm_scheduled_posts(){
+    let now = ic_cdk::api::time();
+    for (id, publish_at) in SCHEDULE_STATE.with(|state| state.borrow().publish_times()){
+        _arm_timer(id, publish_at, now);
+    }
+}
+
+pub(crate) fn _remove_scheduled_posts_of(author: &Principal){
+    for id in SCHEDULE_STATE.with(|state| state.borrow_mut().remove_scheduled_of(author)){
+        _clear_timer(id);
+    }
+}
+
+/// Publishes scheduled post `id` once its timer fires. A post that can no
+/// longer be published, because its author was suspended or hit the daily
+/// limit since scheduling it for example, is dropped and its author notified.
+fn _publish_scheduled_post(scheduled_post_id: u64){
+    SCHEDULE_TIMERS.with(|timers| timers.borrow_mut().remove(&scheduled_post_id));
+    let (author, create_post_data) = match SCHEDULE_STATE.with(|state| state.borrow_mut().take(scheduled_post_id)){
+        None => return,
+        Some(scheduled_post) => scheduled_post
+    };
+    if !matches!(_create_post_for(author, None, create_post_data), CreatePostResponse::Success){
+        _notify(author, NotificationKind::ScheduledPostFailed{ scheduled_post_id });
+    }
+}
+
+impl From<ContentViolation> for SchedulePostResponse{
+    fn from(violation: ContentViolation) -> Self {
+        match violation{
+            ContentViolation::TopicTooLong => SchedulePostResponse::TopicTooLong,
+            ContentViolation::MessageTooLong => SchedulePostResponse::MessageTooLong,
+            ContentViolation::LinksNotAllowed => SchedulePostResponse::LinksNotAllowed,
+            ContentViolation::Rejected(reason) => SchedulePostResponse::ContentRejected{ reason },
+            ContentViolation::MentionBlocked(username) => SchedulePostResponse::MentionBlocked{ username },
+        }
+    }
+}
+
+/// Queues a post to be published at `publish_at`, in nanoseconds. The
+/// content is checked now and the daily limit when it is published.
+#[update]
+#[candid_method(update)]
+pub fn schedule_post(create_post_data: CreatePostData, publish_at: u64) -> SchedulePostResponse{
+    let caller = ic_cdk::caller();
+    if let Err(retry_after_ns) = _check_rate_limit(caller, "schedule_post"){
+        return SchedulePostResponse::RateLimited{ retry_after_ns }
+    }
+    let verification_badge = match get_verification_badge(&caller){
+        None => return SchedulePostResponse::UserNotRegistered,
+        Some(verification_badge) => verification_badge
+    };
+    let now = ic_cdk::api::time();
+    if let Some(until) = _suspended_until(&caller, now){
+        return SchedulePostResponse::Suspended{ until }
+    }
+    if !_badge_capabilities(&verification_badge).can_schedule_posts{
+        return SchedulePostResponse::SchedulingNotAllowed
+    }
+    if publish_at <= now{
+        return SchedulePostResponse::PublishTimeInPast
+    }else if publish_at - now > _max_schedule_ahead(){
+        return SchedulePostResponse::PublishTimeTooFar
+    }
+    if SCHEDULE_STATE.with(|state| state.borrow().count_of(&caller)) as u64 >= _max_scheduled_posts(){
+        return SchedulePostResponse::TooManyScheduledPosts
+    }
+    let create_post_data = create_post_data.normalized();
+    if let Err(e) = _content_check(&caller, &verification_badge, &create_post_data){
+        return e.into()
+    }
+    match SCHEDULE_STATE.with(|state| state.borrow_mut().schedule(caller, create_post_data, publish_at)){
+        None => SchedulePostResponse::FailedToAllocateMemory,
+        Some(scheduled_post_id) => {
+            _arm_timer(scheduled_post_id, publish_at, now);
+            SchedulePostResponse::Success{ scheduled_post_id }
+        }
+    }
+}
+
+#[update]
+#[candid_method(update)]
+pub fn cancel_scheduled_post(scheduled_post_id: u64) -> CancelScheduledPostResponse{
+    let caller = ic_cdk::caller();
+    if !SCHEDULE_STATE.with(|state| state.borrow_mut().cancel(&caller, scheduled_post_id)){
+        return CancelScheduledPostResponse::ScheduledPostNotFound
+    }
+    _clear_timer(scheduled_post_id);
+    CancelScheduledPostResponse::Success
+}
+
+/// The caller's posts waiting to be published, earliest first.
+#[query]
+#[candid_method(query)]
+pub fn get_scheduled_posts() -> Vec<ScheduledPostQuery>{
+    let caller = ic_cdk::caller();
+    SCHEDULE_STATE.with(|state| state.borrow().scheduled_of(&caller))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{testing::{principal, init_memory}, upgrade::tests::upgrade};
+
+    fn post_data(message: &str) -> CreatePostData {
+        CreatePostData{ topic: "topic".to_string(), message: message.to_string() }
+    }
+
+    #[test]
+    fn scheduled_posts_are_kept_in_publish_order() {
+        init_memory();
+        let mut state = ScheduleState::default();
+        let late = state.schedule(principal(1), post_data("late"), 300).unwrap();
+        let early = state.schedule(principal(2), post_data("early"), 100).unwrap();
+        let tied = state.schedule(principal(1), post_data("tied"), 100).unwrap();
+        assert_eq!(state.publish_times(), vec![(early, 100), (tied, 100), (late, 300)]);
+        let ids: Vec<u64> = state.scheduled_of(&principal(1)).iter().map(|post| post.id).collect();
+        assert_eq!(ids, vec![tied, late]);
+        assert!(!state.cancel(&principal(2), late));
+        assert!(state.cancel(&principal(1), late));
+        assert!(state.take(late).is_none());
+    }
+
+    #[test]
+    fn a_scheduled_post_is_published_once() {
+        init_memory();
+        let mut state = ScheduleState::default();
+        let ids: Vec<u64> = ["a", "b", "c"].iter().map(|message| state.schedule(principal(1), post_data(message), 10).unwrap()).collect();
+        let (author, create_post_data) = state.take(ids[1]).unwrap();
+        assert!(author == principal(1) && create_post_data.message == "b");
+        assert!(state.take(ids[1]).is_none());
+        assert_eq!(state.count_of(&principal(1)), 2);
+        assert_eq!(state.remove_scheduled_of(&principal(1)), vec![ids[0], ids[2]]);
+        assert_eq!(state.count_of(&principal(1)), 0);
+    }
+
+    #[test]
+    fn scheduled_posts_survive_an_upgrade() {
+        init_memory();
+        let ids: Vec<u64> = [200, 100].iter().map(|publish_at| SCHEDULE_STATE.with(|state| state.borrow_mut().schedule(principal(1), post_data("later"), *publish_at).unwrap())).collect();
+        upgrade();
+        assert_eq!(SCHEDULE_STATE.with(|state| state.borrow().publish_times()), vec![(ids[1], 100), (ids[0], 200)]);
+        let next = SCHEDULE_STATE.with(|state| state.borrow_mut().schedule(principal(1), post_data("next"), 300).unwrap());
+        assert_eq!(next, 2);
+    }
+}
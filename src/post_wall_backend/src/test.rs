@@ -1,6 +1,7 @@
 use candid::{export_service, Principal, Nat};
+use ic_ledger_types::{AccountIdentifier, Tokens};
 use ic_cdk_macros::*;
-use crate::{registration::*, response::*, post::*};
+use crate::{registration::*, response::*, post::*, config::*, verification_status::*, notification::*, receipt::*, moderation::*, business::*, delegation::*, schedule::*, icrc::Account};
 
 #[query(name = "__get_candid_interface_tmp_hack")]
 fn export_candid() -> String {
@@ -0,0 +1,36 @@
+//! Fixtures shared by the unit tests.
+
+use candid::Principal;
+use ic_stable_memory::SBox;
+
+use crate::{registration::{Profile, Gender, AccountStatus}, verification_status::VerificationBadge};
+
+/// A principal that differs for every `id`.
+pub(crate) fn principal(id: u8) -> Principal {
+    Principal::from_slice(&[id; 29])
+}
+
+/// Gives the test's thread the initialized stable memory every state
+/// structure expects.
+pub(crate) fn init_memory() {
+    ic_stable_memory::stable_memory_init();
+}
+
+/// A regular verified profile for `principal(id)` named `user_{id}`.
+pub(crate) fn profile(id: u8) -> Profile {
+    Profile{
+        first_name: SBox::new(String::new()).unwrap(),
+        middle_name: SBox::new(String::new()).unwrap(),
+        last_name: SBox::new(String::new()).unwrap(),
+        username: SBox::new(format!("user_{id}")).unwrap(),
+        address: principal(id),
+        date_of_birth: 19900101,
+        gender: Gender::RatherNotToSay,
+        verification_badge: VerificationBadge::RegularVerified,
+        verification_expires_at: None,
+        expiry_notified: false,
+        number_of_posts: 0,
+        status: AccountStatus::Active,
+        strikes: 0,
+    }
+}
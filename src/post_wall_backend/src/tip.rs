@@ -0,0 +1,141 @@
+use candid::{candid_method, Nat, Principal};
+use ic_cdk_macros::*;
+use ic_ledger_types::{AccountIdentifier, BlockIndex, DEFAULT_FEE, DEFAULT_SUBACCOUNT};
+
+use crate::{payment::{Ledger, IcpLedger, PaymentError, lock_deposit, unlock_deposit, nat_to_e8s, _send_from_deposit, _send_with_approval}, icrc::{Account, IcrcLedger, Icrc2Ledger}, post::{_visible_post_author, _record_tip}, registration::{get_address, _suspended_until}, relationship::_has_blocked, rate_limit::_check_rate_limit, verification_status::_is_payment_token, config::_tip_fee, receipt::{_issue_receipt, ReceiptKind}, notification::{_notify, NotificationKind}, utils::subaccount_generator, response::TipPostResponse};
+
+/// Pays a tip of `amount` e8s out of the tipper's deposit subaccount: the
+/// author gets `amount - platform_fee` and the treasury `platform_fee`. The
+/// deposit has to cover the ledger fee of every transfer made.
+pub(crate) async fn _tip_from_deposit<L: Ledger>(ledger: &L, canister: Principal, tipper: Principal, author: Principal, amount: u64, platform_fee: u64) -> Result<BlockIndex, PaymentError>{
+    let transfers = if platform_fee > 0{ 2 }else{ 1 };
+    let balance = match ledger.account_balance(AccountIdentifier::new(&canister, &subaccount_generator(&tipper))).await{
+        Ok(balance) => balance,
+        Err(_) => return Err(PaymentError::LedgerError)
+    };
+    if balance.e8s() < amount.saturating_add(transfers * DEFAULT_FEE.e8s()){
+        return Err(PaymentError::InsufficientDeposit)
+    }
+    let block_index = _send_from_deposit(ledger, tipper, AccountIdentifier::new(&author, &DEFAULT_SUBACCOUNT), amount - platform_fee).await?;
+    if platform_fee > 0{
+        // the author has been paid at this point, so a failed fee transfer
+        // only costs the platform its cut
+        let _ = _send_from_deposit(ledger, tipper, AccountIdentifier::new(&canister, &DEFAULT_SUBACCOUNT), platform_fee).await;
+    }
+    Ok(block_index)
+}
+
+/// Pays a tip of `amount` in an ICRC-2 token out of the tipper's allowance,
+/// split between the author and the treasury like `_tip_from_deposit`.
+pub(crate) async fn _tip_with_approval<L: IcrcLedger>(ledger: &L, canister: Principal, tipper: Principal, author: Principal, amount: Nat, platform_fee: Nat, now: u64) -> Result<BlockIndex, PaymentError>{
+    let block_index = _send_with_approval(ledger, tipper, Account::of(author), amount - platform_fee.clone(), now).await?;
+    if platform_fee != 0{
+        let _ = _send_with_approval(ledger, tipper, Account::of(canister), platform_fee, now).await;
+    }
+    Ok(block_index)
+}
+
+/// Tips the author of `post_id` with `amount` of `payment_token`, or of ICP
+/// from the caller's deposit subaccount when it is `None`.
+#[update]
+#[candid_method(update)]
+pub async fn tip_post(post_id: u64, amount: Nat, payment_token: Option<Principal>) -> TipPostResponse{
+    let caller = ic_cdk::caller();
+    if let Err(retry_after_ns) = _check_rate_limit(caller, "tip_post"){
+        return TipPostResponse::RateLimited{ retry_after_ns }
+    }
+    if let Some(until) = _suspended_until(&caller, ic_cdk::api::time()){
+        return TipPostResponse::Suspended{ until }
+    }
+    // hidden posts look the same as missing ones
+    let author = match _visible_post_author(post_id).and_then(|username| get_address(&username)){
+        None => return TipPostResponse::PostNotFound,
+        Some(author) => author
+    };
+    // the author's posts are out of sight for anyone they blocked
+    if _has_blocked(&author, &caller){
+        return TipPostResponse::PostNotFound
+    }
+    if author == caller{
+        return TipPostResponse::CannotTipOwnPost
+    }
+    if amount == 0{
+        return TipPostResponse::InvalidAmount
+    }
+    if let Some(ledger) = payment_token{
+        if !_is_payment_token(&ledger){
+            return TipPostResponse::UnsupportedPaymentToken
+        }
+    }
+    let platform_fee = _tip_fee(&amount);
+    if !lock_deposit(caller){
+        return TipPostResponse::PaymentInProgress
+    }
+    let result = match payment_token{
+        None => match (nat_to_e8s(&amount), nat_to_e8s(&platform_fee)){
+            (Some(e8s), Some(fee_e8s)) => _tip_from_deposit(&IcpLedger::configured(), ic_cdk::id(), caller, author, e8s, fee_e8s).await,
+            _ => Err(PaymentError::AmountTooLarge)
+        },
+        Some(canister_id) => _tip_with_approval(&Icrc2Ledger{ canister_id }, ic_cdk::id(), caller, author, amount.clone(), platform_fee, ic_cdk::api::time()).await,
+    };
+    unlock_deposit(&caller);
+    let block_index = match result{
+        Ok(block_index) => block_index,
+        Err(PaymentError::AmountTooLarge) => return TipPostResponse::InvalidAmount,
+        Err(PaymentError::InsufficientDeposit) => return TipPostResponse::InsufficientFunds,
+        Err(PaymentError::PaymentInProgress) => return TipPostResponse::PaymentInProgress,
+        Err(PaymentError::LedgerError) => return TipPostResponse::PaymentFailed,
+    };
+    // the post may have been removed while the transfer was in flight; the
+    // tip still stands and keeps its receipt
+    _record_tip(post_id, payment_token, &amount);
+    _issue_receipt(caller, ReceiptKind::Tip{ post_id }, block_index, amount.clone(), payment_token, None);
+    _notify(author, NotificationKind::PostTipped{ post_id, amount, token: payment_token });
+    TipPostResponse::Success{ block_index }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::principal;
+    use crate::mock_ledger::{block_on, MockIcrcLedger, MockLedger};
+
+    const CANISTER: u8 = 100;
+
+    fn account(id: u8) -> AccountIdentifier {
+        AccountIdentifier::new(&principal(id), &DEFAULT_SUBACCOUNT)
+    }
+
+    #[test]
+    fn icp_tips_are_split_between_the_author_and_the_treasury() {
+        let ledger = MockLedger::new(principal(CANISTER));
+        let deposit = AccountIdentifier::new(&principal(CANISTER), &subaccount_generator(&principal(2)));
+        ledger.deposit(deposit, 100_000 + 2 * DEFAULT_FEE.e8s());
+        let result = block_on(_tip_from_deposit(&ledger, principal(CANISTER), principal(2), principal(3), 100_000, 5_000));
+        assert!(result.is_ok());
+        assert_eq!(ledger.balance(&account(3)), 95_000);
+        assert_eq!(ledger.balance(&account(CANISTER)), 5_000);
+        assert_eq!(ledger.balance(&deposit), 0);
+    }
+
+    #[test]
+    fn icp_tips_need_a_deposit_covering_every_transfer_fee() {
+        let ledger = MockLedger::new(principal(CANISTER));
+        let deposit = AccountIdentifier::new(&principal(CANISTER), &subaccount_generator(&principal(2)));
+        ledger.deposit(deposit, 100_000 + DEFAULT_FEE.e8s());
+        let result = block_on(_tip_from_deposit(&ledger, principal(CANISTER), principal(2), principal(3), 100_000, 5_000));
+        assert!(matches!(result, Err(PaymentError::InsufficientDeposit)));
+        assert_eq!(ledger.transfer_count(), 0);
+    }
+
+    #[test]
+    fn token_tips_are_paid_from_the_allowance() {
+        let ledger = MockIcrcLedger::new(principal(CANISTER));
+        ledger.mint(principal(2), 1_000);
+        ledger.approve(principal(2), 1_000);
+        let result = block_on(_tip_with_approval(&ledger, principal(CANISTER), principal(2), principal(3), Nat::from(500), Nat::from(0), 0));
+        assert!(result.is_ok());
+        assert_eq!(ledger.balance(&principal(3)), 500);
+        assert_eq!(ledger.balance(&principal(2)), 500);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,431 @@
+use std::{cell::RefCell, collections::BTreeSet};
+
+use candid::{CandidType, candid_method, Nat, Principal};
+use ic_cdk_macros::*;
+use ic_ledger_types::{AccountIdentifier, Memo, Timestamp, Tokens, TransferArgs, TransferError, DEFAULT_FEE, DEFAULT_SUBACCOUNT};
+use ic_stable_memory::{derive::{AsFixedSizeBytes, StableType}, collections::SVec};
+
+use crate::{init::is_this_caller_authority, payment::{Ledger, IcpLedger, nat_to_e8s}, icrc::{Account, IcrcLedger, Icrc2Ledger, TransferArg, TransferError as IcrcTransferError}, response::{UpdateStateResponse, TreasuryBalanceResponse, WithdrawResponse, WithdrawalHistoryResponse}};
+
+/// Where a withdrawal was sent: an ICP account identifier, or an account
+/// on the ICRC-1 ledger of the withdrawal's token.
+#[derive(StableType, AsFixedSizeBytes, Clone, PartialEq)]
+pub enum Destination{
+    AccountIdentifier([u8; 32]),
+    Account{ owner: Principal, subaccount: Option<[u8; 32]> },
+}
+
+#[derive(CandidType)]
+pub enum DestinationQuery{
+    AccountIdentifier(AccountIdentifier),
+    Account(Account),
+}
+
+impl Destination{
+    fn of_account_identifier(account_identifier: &AccountIdentifier) -> Self{
+        let mut destination = [0; 32];
+        destination.copy_from_slice(account_identifier.as_ref());
+        Destination::AccountIdentifier(destination)
+    }
+
+    fn of_account(account: &Account) -> Self{
+        Destination::Account{ owner: account.owner, subaccount: account.subaccount }
+    }
+
+    fn _to_destination_query(&self) -> Option<DestinationQuery>{
+        match self{
+            Destination::AccountIdentifier(destination) => AccountIdentifier::try_from(*destination).ok().map(DestinationQuery::AccountIdentifier),
+            Destination::Account{ owner, subaccount } => Some(DestinationQuery::Account(Account{ owner: *owner, subaccount: *subaccount })),
+        }
+    }
+}
+
+/// A withdrawal from the treasury. `token` is `None` for ICP, in which case
+/// `amount` is in e8s; otherwise it is in the token's smallest unit.
+#[derive(StableType, AsFixedSizeBytes, Clone)]
+pub struct Withdrawal{
+    pub memo: u64,
+    pub token: Option<Principal>,
+    pub to: Destination,
+    pub amount: Nat,
+    pub block_index: u64,
+    pub requested_by: Principal,
+    pub withdrawn_at: u64,
+}
+
+#[derive(CandidType)]
+pub struct WithdrawalQuery{
+    pub memo: u64,
+    pub token: Option<Principal>,
+    pub to: DestinationQuery,
+    pub amount: Nat,
+    pub block_index: u64,
+    pub requested_by: Principal,
+    pub withdrawn_at: u64,
+}
+
+impl Withdrawal{
+    fn _to_withdrawal_query(&self) -> Option<WithdrawalQuery>{
+        Some(WithdrawalQuery{
+            memo: self.memo,
+            token: self.token,
+            to: self.to._to_destination_query()?,
+            amount: self.amount.clone(),
+            block_index: self.block_index,
+            requested_by: self.requested_by,
+            withdrawn_at: self.withdrawn_at,
+        })
+    }
+}
+
+#[derive(StableType, AsFixedSizeBytes)]
+pub struct TreasuryState{
+    pub treasurers: SVec<Principal>,
+    pub withdrawals: SVec<Withdrawal>,
+}
+
+impl Default for TreasuryState{
+    fn default() -> Self {
+        Self{
+            treasurers: SVec::new(),
+            withdrawals: SVec::new(),
+        }
+    }
+}
+
+impl TreasuryState{
+    fn is_treasurer(&self, address: &Principal) -> bool{
+        self.treasurers.iter().any(|treasurer| *treasurer == *address)
+    }
+
+    fn add_treasurer(&mut self, address: Principal) -> bool{
+        if self.is_treasurer(&address){
+            return true
+        }
+        self.treasurers.push(address).is_ok()
+    }
+
+    fn remove_treasurer(&mut self, address: &Principal){
+        if let Some(index) = self.treasurers.iter().position(|treasurer| *treasurer == *address){
+            self.treasurers.remove(index);
+        }
+    }
+
+    /// Memos are scoped to a token, so ICP and each token count their own.
+    fn withdrawal_by_memo(&self, token: &Option<Principal>, memo: u64) -> Option<Withdrawal>{
+        self.withdrawals.iter().find(|withdrawal| withdrawal.token == *token && withdrawal.memo == memo).map(|withdrawal| withdrawal.clone())
+    }
+}
+
+thread_local! {
+    pub static TREASURY_STATE: RefCell<TreasuryState> = RefCell::default();
+    static WITHDRAWALS_IN_PROGRESS: RefCell<BTreeSet<(Option<Principal>, u64)>> = RefCell::default();
+}
+
+pub(crate) fn can_manage_treasury(caller: &Principal) -> bool{
+    is_this_caller_authority(caller) || TREASURY_STATE.with(|state| state.borrow().is_treasurer(caller))
+}
+
+pub(crate) async fn _treasury_balance<L: Ledger>(ledger: &L, canister: Principal) -> Result<Nat, String>{
+    ledger.account_balance(AccountIdentifier::new(&canister, &DEFAULT_SUBACCOUNT)).await.map(|balance| Nat::from(balance.e8s()))
+}
+
+pub(crate) async fn _token_balance<L: IcrcLedger>(ledger: &L, canister: Principal) -> Result<Nat, String>{
+    ledger.balance_of(Account::of(canister)).await
+}
+
+/// Checks the caller may withdraw and reserves `memo` for the transfer. A
+/// memo that already has a recorded withdrawal of `token` yields that
+/// withdrawal's block index instead, provided it went to the same
+/// destination for the same amount.
+fn _reserve_withdrawal(caller: &Principal, token: &Option<Principal>, to: &Destination, amount: &Nat, memo: u64) -> Result<(), WithdrawResponse>{
+    if !can_manage_treasury(caller){
+        return Err(WithdrawResponse::Unauthorized)
+    }
+    if let Some(withdrawal) = TREASURY_STATE.with(|state| state.borrow().withdrawal_by_memo(token, memo)){
+        if withdrawal.to != *to || withdrawal.amount != *amount{
+            return Err(WithdrawResponse::MemoAlreadyUsed)
+        }
+        return Err(WithdrawResponse::Success{ block_index: withdrawal.block_index })
+    }
+    let reserved = WITHDRAWALS_IN_PROGRESS.with(|state| state.borrow_mut().insert((*token, memo)));
+    if !reserved{
+        return Err(WithdrawResponse::WithdrawalInProgress)
+    }
+    Ok(())
+}
+
+/// Releases the memo `_reserve_withdrawal` reserved and records the
+/// withdrawal if the ledger made the transfer.
+fn _complete_withdrawal(withdrawal: Withdrawal, transferred: Result<u64, WithdrawResponse>) -> WithdrawResponse{
+    WITHDRAWALS_IN_PROGRESS.with(|state| state.borrow_mut().remove(&(withdrawal.token, withdrawal.memo)));
+    let block_index = match transferred{
+        Ok(block_index) => block_index,
+        Err(response) => return response
+    };
+    // the transfer already happened, so a full history does not turn it into an error
+    TREASURY_STATE.with(|state| state.borrow_mut().withdrawals.push(Withdrawal{ block_index, ..withdrawal }).ok());
+    WithdrawResponse::Success{ block_index }
+}
+
+/// Sends `amount` ICP from the canister's default account to `to`.
+pub(crate) async fn _withdraw<L: Ledger>(ledger: &L, caller: Principal, to: AccountIdentifier, amount: Tokens, memo: u64, now: u64) -> WithdrawResponse{
+    let withdrawal = Withdrawal{
+        memo,
+        token: None,
+        to: Destination::of_account_identifier(&to),
+        amount: Nat::from(amount.e8s()),
+        block_index: 0,
+        requested_by: caller,
+        withdrawn_at: now,
+    };
+    if let Err(response) = _reserve_withdrawal(&caller, &withdrawal.token, &withdrawal.to, &withdrawal.amount, memo){
+        return response
+    }
+    let transfer_args = TransferArgs{
+        memo: Memo(memo),
+        amount,
+        fee: DEFAULT_FEE,
+        from_subaccount: None,
+        to,
+        created_at_time: Some(Timestamp{ timestamp_nanos: now }),
+    };
+    let transferred = match ledger.transfer(transfer_args).await{
+        Ok(Ok(block_index)) => Ok(block_index),
+        Ok(Err(TransferError::TxDuplicate{ duplicate_of })) => Ok(duplicate_of),
+        Ok(Err(TransferError::InsufficientFunds{ balance })) => Err(WithdrawResponse::InsufficientFunds{ balance: Nat::from(balance.e8s()) }),
+        Ok(Err(e)) => Err(WithdrawResponse::TransferFailed(e.to_string())),
+        Err(message) => Err(WithdrawResponse::LedgerError(message)),
+    };
+    _complete_withdrawal(withdrawal, transferred)
+}
+
+/// Sends `amount` of the token held by `ledger` from the canister's default
+/// account to `to`.
+pub(crate) async fn _withdraw_token<L: IcrcLedger>(ledger: &L, token: Principal, caller: Principal, to: Account, amount: Nat, memo: u64, now: u64) -> WithdrawResponse{
+    let withdrawal = Withdrawal{
+        memo,
+        token: Some(token),
+        to: Destination::of_account(&to),
+        amount: amount.clone(),
+        block_index: 0,
+        requested_by: caller,
+        withdrawn_at: now,
+    };
+    if let Err(response) = _reserve_withdrawal(&caller, &withdrawal.token, &withdrawal.to, &withdrawal.amount, memo){
+        return response
+    }
+    let transfer_arg = TransferArg{
+        from_subaccount: None,
+        to,
+        amount,
+        fee: None,
+        memo: Some(memo.to_be_bytes().to_vec()),
+        created_at_time: Some(now),
+    };
+    let transferred = match ledger.transfer(transfer_arg).await{
+        Ok(Ok(block_index)) | Ok(Err(IcrcTransferError::Duplicate{ duplicate_of: block_index })) => nat_to_e8s(&block_index).ok_or_else(|| WithdrawResponse::LedgerError(format!("block index {block_index} out of range"))),
+        Ok(Err(IcrcTransferError::InsufficientFunds{ balance })) => Err(WithdrawResponse::InsufficientFunds{ balance }),
+        Ok(Err(e)) => Err(WithdrawResponse::TransferFailed(format!("{e:?}"))),
+        Err(message) => Err(WithdrawResponse::LedgerError(message)),
+    };
+    _complete_withdrawal(withdrawal, transferred)
+}
+
+fn _withdrawals(start: usize, limit: usize) -> Vec<WithdrawalQuery>{
+    TREASURY_STATE.with(|state|{
+        let state = state.borrow();
+        state.withdrawals.iter().skip(start).take(limit).filter_map(|withdrawal| withdrawal._to_withdrawal_query()).collect()
+    })
+}
+
+#[update]
+#[candid_method(update)]
+pub async fn treasury_balance(token: Option<Principal>) -> TreasuryBalanceResponse{
+    let caller = ic_cdk::caller();
+    if !can_manage_treasury(&caller){
+        return TreasuryBalanceResponse::Unauthorized
+    }
+    let balance = match token{
+        None => _treasury_balance(&IcpLedger::configured(), ic_cdk::id()).await,
+        Some(canister_id) => _token_balance(&Icrc2Ledger{ canister_id }, ic_cdk::id()).await,
+    };
+    match balance{
+        Ok(balance) => TreasuryBalanceResponse::Success(balance),
+        Err(message) => TreasuryBalanceResponse::LedgerError(message),
+    }
+}
+
+#[update]
+#[candid_method(update)]
+pub async fn withdraw(to_account: AccountIdentifier, amount: Tokens, memo: u64) -> WithdrawResponse{
+    let caller = ic_cdk::caller();
+    _withdraw(&IcpLedger::configured(), caller, to_account, amount, memo, ic_cdk::api::time()).await
+}
+
+#[update]
+#[candid_method(update)]
+pub async fn withdraw_token(token: Principal, to_account: Account, amount: Nat, memo: u64) -> WithdrawResponse{
+    let caller = ic_cdk::caller();
+    _withdraw_token(&Icrc2Ledger{ canister_id: token }, token, caller, to_account, amount, memo, ic_cdk::api::time()).await
+}
+
+#[update]
+#[candid_method(update)]
+pub fn add_treasurer(treasurer: Principal) -> UpdateStateResponse{
+    let caller = ic_cdk::caller();
+    if !is_this_caller_authority(&caller){
+        return UpdateStateResponse::Unauthorized
+    }
+    TREASURY_STATE.with(|state| state.borrow_mut().add_treasurer(treasurer));
+    UpdateStateResponse::Success
+}
+
+#[update]
+#[candid_method(update)]
+pub fn remove_treasurer(treasurer: Principal) -> UpdateStateResponse{
+    let caller = ic_cdk::caller();
+    if !is_this_caller_authority(&caller){
+        return UpdateStateResponse::Unauthorized
+    }
+    TREASURY_STATE.with(|state| state.borrow_mut().remove_treasurer(&treasurer));
+    UpdateStateResponse::Success
+}
+
+#[query]
+#[candid_method(query)]
+pub fn get_withdrawals(start: u64, limit: u64) -> WithdrawalHistoryResponse{
+    let caller = ic_cdk::caller();
+    if !can_manage_treasury(&caller){
+        return WithdrawalHistoryResponse::Unauthorized
+    }
+    WithdrawalHistoryResponse::Success(_withdrawals(start as usize, limit as usize))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{init::_change_authorty, mock_ledger::{block_on, MockLedger, MockIcrcLedger}, upgrade::tests::upgrade};
+    use ic_ledger_types::Subaccount;
+    use crate::testing::{principal, init_memory};
+
+    fn account(id: u8) -> AccountIdentifier {
+        AccountIdentifier::new(&principal(id), &DEFAULT_SUBACCOUNT)
+    }
+
+    fn setup() -> MockLedger {
+        init_memory();
+        _change_authorty(principal(1));
+        let ledger = MockLedger::new(principal(100));
+        ledger.deposit(AccountIdentifier::new(&principal(100), &DEFAULT_SUBACCOUNT), 1_000_000);
+        ledger
+    }
+
+    #[test]
+    fn treasury_balance_reads_the_default_account() {
+        let ledger = setup();
+        ledger.deposit(AccountIdentifier::new(&principal(100), &Subaccount([7; 32])), 5_000);
+        let balance = block_on(_treasury_balance(&ledger, principal(100))).unwrap();
+        assert_eq!(balance, Nat::from(1_000_000));
+    }
+
+    #[test]
+    fn withdraw_transfers_and_records_the_withdrawal() {
+        let ledger = setup();
+        let response = block_on(_withdraw(&ledger, principal(1), account(2), Tokens::from_e8s(400_000), 42, 10));
+        assert!(matches!(response, WithdrawResponse::Success{ block_index: 0 }));
+        assert_eq!(ledger.balance(&account(2)), 400_000);
+        assert_eq!(ledger.balance(&AccountIdentifier::new(&principal(100), &DEFAULT_SUBACCOUNT)), 1_000_000 - 400_000 - DEFAULT_FEE.e8s());
+        let history = _withdrawals(0, 10);
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].memo, 42);
+        assert!(matches!(&history[0].to, DestinationQuery::AccountIdentifier(to) if *to == account(2)));
+        assert_eq!(history[0].requested_by, principal(1));
+    }
+
+    #[test]
+    fn withdraw_with_a_used_memo_does_not_transfer_twice() {
+        let ledger = setup();
+        block_on(_withdraw(&ledger, principal(1), account(2), Tokens::from_e8s(100_000), 7, 10));
+        let response = block_on(_withdraw(&ledger, principal(1), account(2), Tokens::from_e8s(100_000), 7, 20));
+        assert!(matches!(response, WithdrawResponse::Success{ block_index: 0 }));
+        assert_eq!(ledger.transfer_count(), 1);
+        assert_eq!(ledger.balance(&account(2)), 100_000);
+        let response = block_on(_withdraw(&ledger, principal(1), account(3), Tokens::from_e8s(100_000), 7, 30));
+        assert!(matches!(response, WithdrawResponse::MemoAlreadyUsed));
+        let response = block_on(_withdraw(&ledger, principal(1), account(2), Tokens::from_e8s(200_000), 7, 40));
+        assert!(matches!(response, WithdrawResponse::MemoAlreadyUsed));
+        assert_eq!(ledger.transfer_count(), 1);
+        assert_eq!(_withdrawals(0, 10).len(), 1);
+    }
+
+    #[test]
+    fn withdraw_rejects_callers_without_the_treasurer_role() {
+        let ledger = setup();
+        let response = block_on(_withdraw(&ledger, principal(3), account(3), Tokens::from_e8s(100_000), 1, 10));
+        assert!(matches!(response, WithdrawResponse::Unauthorized));
+        assert_eq!(ledger.transfer_count(), 0);
+    }
+
+    #[test]
+    fn treasurers_can_withdraw_until_removed() {
+        let ledger = setup();
+        TREASURY_STATE.with(|state| state.borrow_mut().add_treasurer(principal(3)));
+        let response = block_on(_withdraw(&ledger, principal(3), account(3), Tokens::from_e8s(100_000), 1, 10));
+        assert!(matches!(response, WithdrawResponse::Success{ .. }));
+        TREASURY_STATE.with(|state| state.borrow_mut().remove_treasurer(&principal(3)));
+        let response = block_on(_withdraw(&ledger, principal(3), account(3), Tokens::from_e8s(100_000), 2, 10));
+        assert!(matches!(response, WithdrawResponse::Unauthorized));
+    }
+
+    #[test]
+    fn withdraw_reports_insufficient_funds_without_recording() {
+        let ledger = setup();
+        let response = block_on(_withdraw(&ledger, principal(1), account(2), Tokens::from_e8s(5_000_000), 3, 10));
+        assert!(matches!(response, WithdrawResponse::InsufficientFunds{ balance } if nat_to_e8s(&balance) == Some(1_000_000)));
+        assert!(_withdrawals(0, 10).is_empty());
+    }
+
+    #[test]
+    fn token_withdrawals_use_the_token_ledger_and_their_own_memos() {
+        let ledger = setup();
+        let token_ledger = MockIcrcLedger::new(principal(100));
+        token_ledger.mint(principal(100), 1_000);
+        assert_eq!(block_on(_token_balance(&token_ledger, principal(100))).unwrap(), Nat::from(1_000));
+        block_on(_withdraw(&ledger, principal(1), account(2), Tokens::from_e8s(100_000), 9, 10));
+        let response = block_on(_withdraw_token(&token_ledger, principal(50), principal(1), Account::of(principal(2)), Nat::from(400), 9, 20));
+        assert!(matches!(response, WithdrawResponse::Success{ block_index: 0 }));
+        assert_eq!(token_ledger.balance(&principal(2)), 400);
+        let response = block_on(_withdraw_token(&token_ledger, principal(50), principal(1), Account::of(principal(2)), Nat::from(400), 9, 30));
+        assert!(matches!(response, WithdrawResponse::Success{ block_index: 0 }));
+        assert_eq!(token_ledger.balance(&principal(100)), 600);
+        let history = _withdrawals(0, 10);
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[1].token, Some(principal(50)));
+        assert_eq!(history[1].amount, Nat::from(400));
+    }
+
+    #[test]
+    fn token_withdrawals_above_u64_reach_the_ledger() {
+        let _ledger = setup();
+        let token_ledger = MockIcrcLedger::new(principal(100));
+        let amount = Nat::from(u64::MAX) + Nat::from(1);
+        let response = block_on(_withdraw_token(&token_ledger, principal(50), principal(1), Account::of(principal(2)), amount, 1, 10));
+        assert!(matches!(response, WithdrawResponse::LedgerError(_)));
+        assert!(_withdrawals(0, 10).is_empty());
+    }
+
+    #[test]
+    fn treasurers_and_withdrawal_memos_survive_an_upgrade() {
+        let ledger = setup();
+        TREASURY_STATE.with(|state| state.borrow_mut().add_treasurer(principal(3)));
+        block_on(_withdraw(&ledger, principal(3), account(2), Tokens::from_e8s(100_000), 7, 10));
+        upgrade();
+        assert_eq!(_withdrawals(0, 10).len(), 1);
+        let response = block_on(_withdraw(&ledger, principal(3), account(2), Tokens::from_e8s(100_000), 7, 20));
+        assert!(matches!(response, WithdrawResponse::Success{ block_index: 0 }));
+        assert_eq!(ledger.transfer_count(), 1);
+        let response = block_on(_withdraw(&ledger, principal(3), account(3), Tokens::from_e8s(100_000), 7, 30));
+        assert!(matches!(response, WithdrawResponse::MemoAlreadyUsed));
+    }
+}
@@ -0,0 +1,152 @@
+use std::{cell::RefCell, thread::LocalKey};
+
+use ic_stable_memory::{SBox, AsFixedSizeBytes, StableType, store_custom_data, retrieve_custom_data, collections::SBTreeMap};
+
+use crate::{init::INIT_DATA, config::CONFIG, registration::USER_PROFILE, post::POST_STATE, verification_status::{VERIFICATION_CHARGE, USER_COUNT, BADGE_AUDIT_LOG, TOKEN_VERIFICATION_CHARGE}, notification::NOTIFICATION_STATE, treasury::TREASURY_STATE, receipt::RECEIPT_STATE, moderation::MODERATION_STATE, relationship::RELATIONSHIP_STATE, content_filter::FILTER_STATE, business::BUSINESS_STATE, username::USERNAME_STATE, delegation::DELEGATION_STATE, schedule::SCHEDULE_STATE};
+
+// Slots the state roots are kept under between `pre_upgrade` and
+// `post_upgrade`. A slot is never reused for a different type.
+const INIT_DATA_SLOT: usize = 0;
+const CONFIG_SLOT: usize = 1;
+const USER_PROFILE_SLOT: usize = 2;
+const POST_STATE_SLOT: usize = 3;
+const VERIFICATION_CHARGE_SLOT: usize = 4;
+const USER_COUNT_SLOT: usize = 5;
+const BADGE_AUDIT_LOG_SLOT: usize = 6;
+const NOTIFICATION_STATE_SLOT: usize = 7;
+const TREASURY_STATE_SLOT: usize = 8;
+const TOKEN_VERIFICATION_CHARGE_SLOT: usize = 9;
+const RECEIPT_STATE_SLOT: usize = 10;
+const MODERATION_STATE_SLOT: usize = 11;
+const RELATIONSHIP_STATE_SLOT: usize = 12;
+const FILTER_STATE_SLOT: usize = 13;
+const BUSINESS_STATE_SLOT: usize = 14;
+const USERNAME_STATE_SLOT: usize = 15;
+const DELEGATION_STATE_SLOT: usize = 16;
+const SCHEDULE_STATE_SLOT: usize = 17;
+
+/// Moves `value` into stable memory under `slot`.
+fn _store<T: StableType + AsFixedSizeBytes>(slot: usize, value: T){
+    match SBox::new(value){
+        Ok(boxed) => store_custom_data(slot, boxed),
+        Err(_) => panic!("out of stable memory saving state slot {slot}"),
+    }
+}
+
+/// The value saved under `slot` by the previous version, if it saved one.
+fn _retrieve<T: StableType + AsFixedSizeBytes>(slot: usize) -> Option<T>{
+    retrieve_custom_data::<T>(slot).map(|boxed| boxed.into_inner())
+}
+
+fn _save<T: StableType + AsFixedSizeBytes + Default>(slot: usize, state: &'static LocalKey<RefCell<T>>){
+    _store(slot, state.with(|state| state.take()));
+}
+
+fn _restore<T: StableType + AsFixedSizeBytes>(slot: usize, state: &'static LocalKey<RefCell<T>>){
+    if let Some(saved) = _retrieve(slot){
+        state.with(|state| *state.borrow_mut() = saved);
+    }
+}
+
+/// Hands every state root to stable memory. Must be followed by
+/// `stable_memory_pre_upgrade`, after which the state is no longer usable.
+pub(crate) fn _save_state(){
+    _save(INIT_DATA_SLOT, &INIT_DATA);
+    _save(CONFIG_SLOT, &CONFIG);
+    _save(USER_PROFILE_SLOT, &USER_PROFILE);
+    _save(POST_STATE_SLOT, &POST_STATE);
+    _save(VERIFICATION_CHARGE_SLOT, &VERIFICATION_CHARGE);
+    _save(USER_COUNT_SLOT, &USER_COUNT);
+    _save(BADGE_AUDIT_LOG_SLOT, &BADGE_AUDIT_LOG);
+    _save(NOTIFICATION_STATE_SLOT, &NOTIFICATION_STATE);
+    _save(TREASURY_STATE_SLOT, &TREASURY_STATE);
+    // SBTreeMap has no Default to take() with
+    _store(TOKEN_VERIFICATION_CHARGE_SLOT, TOKEN_VERIFICATION_CHARGE.with(|state| state.replace(SBTreeMap::new())));
+    _save(RECEIPT_STATE_SLOT, &RECEIPT_STATE);
+    _save(MODERATION_STATE_SLOT, &MODERATION_STATE);
+    _save(RELATIONSHIP_STATE_SLOT, &RELATIONSHIP_STATE);
+    _save(FILTER_STATE_SLOT, &FILTER_STATE);
+    _save(BUSINESS_STATE_SLOT, &BUSINESS_STATE);
+    _save(USERNAME_STATE_SLOT, &USERNAME_STATE);
+    _save(DELEGATION_STATE_SLOT, &DELEGATION_STATE);
+    _save(SCHEDULE_STATE_SLOT, &SCHEDULE_STATE);
+}
+
+/// Puts back the state roots `_save_state` stored. Must follow
+/// `stable_memory_post_upgrade`. State the previous version did not save
+/// keeps its default.
+pub(crate) fn _restore_state(){
+    _restore(INIT_DATA_SLOT, &INIT_DATA);
+    _restore(CONFIG_SLOT, &CONFIG);
+    _restore(USER_PROFILE_SLOT, &USER_PROFILE);
+    _restore(POST_STATE_SLOT, &POST_STATE);
+    _restore(VERIFICATION_CHARGE_SLOT, &VERIFICATION_CHARGE);
+    _restore(USER_COUNT_SLOT, &USER_COUNT);
+    _restore(BADGE_AUDIT_LOG_SLOT, &BADGE_AUDIT_LOG);
+    _restore(NOTIFICATION_STATE_SLOT, &NOTIFICATION_STATE);
+    _restore(TREASURY_STATE_SLOT, &TREASURY_STATE);
+    _restore(TOKEN_VERIFICATION_CHARGE_SLOT, &TOKEN_VERIFICATION_CHARGE);
+    _restore(RECEIPT_STATE_SLOT, &RECEIPT_STATE);
+    _restore(MODERATION_STATE_SLOT, &MODERATION_STATE);
+    _restore(RELATIONSHIP_STATE_SLOT, &RELATIONSHIP_STATE);
+    _restore(FILTER_STATE_SLOT, &FILTER_STATE);
+    _restore(BUSINESS_STATE_SLOT, &BUSINESS_STATE);
+    _restore(USERNAME_STATE_SLOT, &USERNAME_STATE);
+    _restore(DELEGATION_STATE_SLOT, &DELEGATION_STATE);
+    _restore(SCHEDULE_STATE_SLOT, &SCHEDULE_STATE);
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{config::{Config, _query_config, _update_config}, registration::{Profile, _register_profile, _expiring_verifications}, verification_status::{BadgeChange, VerificationBadge, _set_token_verification_charge, _verification_charge_in}, notification::{Notification, NotificationKind}, testing::{principal, profile, init_memory}};
+    use ic_stable_memory::{stable_memory_pre_upgrade, stable_memory_post_upgrade};
+
+    /// Runs the same steps as the `pre_upgrade` and `post_upgrade` hooks.
+    pub(crate) fn upgrade(){
+        _save_state();
+        stable_memory_pre_upgrade().unwrap();
+        stable_memory_post_upgrade();
+        _restore_state();
+    }
+
+    #[test]
+    fn config_survives_an_upgrade() {
+        init_memory();
+        _update_config(Config{ minimum_age: 16, tip_fee_basis_points: 250, ..Config::default() });
+        upgrade();
+        let config = _query_config();
+        assert_eq!(config.minimum_age, 16);
+        assert_eq!(config.tip_fee_basis_points, 250);
+    }
+
+    #[test]
+    fn verification_expiry_badge_log_and_notifications_survive_an_upgrade() {
+        init_memory();
+        assert!(_register_profile(Profile{ verification_badge: VerificationBadge::PlusVerified, verification_expires_at: Some(1_000), ..profile(2) }));
+        let change = BadgeChange{
+            address: principal(2),
+            from: VerificationBadge::RegularVerified,
+            to: VerificationBadge::PlusVerified,
+            changed_by: principal(2),
+            amount_paid: None,
+            block_index: None,
+            changed_at: 10,
+        };
+        assert!(BADGE_AUDIT_LOG.with(|state| state.borrow_mut().push(change).is_ok()));
+        let notification = Notification{ kind: NotificationKind::VerificationExpiringSoon{ expires_at: 1_000 }, created_at: 10 };
+        assert!(NOTIFICATION_STATE.with(|state| state.borrow_mut().notify(principal(2), notification)));
+        upgrade();
+        assert_eq!(_expiring_verifications(), vec![(principal(2), 1_000, false)]);
+        assert_eq!(BADGE_AUDIT_LOG.with(|state| state.borrow().len()), 1);
+        assert_eq!(NOTIFICATION_STATE.with(|state| state.borrow().notifications.get(&principal(2)).map(|inbox| inbox.len())), Some(1));
+    }
+
+    #[test]
+    fn token_verification_charges_survive_an_upgrade() {
+        init_memory();
+        assert!(_set_token_verification_charge(principal(50), &VerificationBadge::PlusVerified, Some(candid::Nat::from(700))));
+        upgrade();
+        assert_eq!(_verification_charge_in(&VerificationBadge::PlusVerified, &Some(principal(50))), Some(candid::Nat::from(700)));
+    }
+}
@@ -0,0 +1,180 @@
+use std::cell::RefCell;
+
+use candid::candid_method;
+use ic_cdk_macros::*;
+use ic_stable_memory::{SBox, derive::{AsFixedSizeBytes, StableType}, collections::SVec};
+
+use crate::{init::is_this_caller_authority, config::_profile_limits, response::{RegistrationResponse, UpdateStateResponse}};
+
+/// Names no one may register, whatever the reserved list holds.
+const BUILT_IN_RESERVED_USERNAMES: [&str; 6] = ["admin", "administrator", "support", "moderator", "root", "system"];
+
+/// Usernames kept back by the authority, such as brand names, stored folded.
+#[derive(StableType, AsFixedSizeBytes)]
+pub struct UsernameState{
+    pub reserved: SVec<SBox<String>>,
+}
+
+impl Default for UsernameState{
+    fn default() -> Self {
+        Self{
+            reserved: SVec::new(),
+        }
+    }
+}
+
+impl UsernameState{
+    fn add_reserved(&mut self, username: String) -> bool{
+        if self.reserved.iter().any(|reserved| **reserved == username){
+            return true
+        }
+        match SBox::new(username){
+            Ok(username) => self.reserved.push(username).is_ok(),
+            Err(_) => false
+        }
+    }
+
+    fn remove_reserved(&mut self, username: &String){
+        if let Some(index) = self.reserved.iter().position(|reserved| **reserved == *username){
+            self.reserved.remove(index);
+        }
+    }
+
+    fn is_reserved(&self, folded: &String) -> bool{
+        BUILT_IN_RESERVED_USERNAMES.iter().any(|reserved| fold_username(reserved) == *folded)
+            || self.reserved.iter().any(|reserved| **reserved == *folded)
+    }
+}
+
+thread_local! {
+    pub static USERNAME_STATE: RefCell<UsernameState> = RefCell::default();
+}
+
+/// Digits drawn like a letter, with the letter each one folds into. A digit
+/// is only listed when it passes for the letter in the usual fonts, and
+/// letters are never folded into one another: `l`, `i` and `I` stay three
+/// names apart after lowercasing turns `I` into `i`.
+const CONFUSABLE_DIGITS: [(char, char); 3] = [('0', 'o'), ('1', 'l'), ('5', 's')];
+
+/// The form two usernames are compared in: lowercased, with every digit of
+/// `CONFUSABLE_DIGITS` replaced by its letter, so `Alice`, `ALICE` and
+/// `a1ice` are all the same name.
+pub(crate) fn fold_username(username: &str) -> String{
+    username.chars().map(|c|{
+        let c = c.to_ascii_lowercase();
+        CONFUSABLE_DIGITS.iter().find(|(digit, _)| *digit == c).map_or(c, |(_, letter)| *letter)
+    }).collect()
+}
+
+/// Checks `username` against the naming rules: ASCII letters, digits and
+/// underscores only, not starting with a digit, within the configured length
+/// and not reserved. Whether it is taken is up to the caller.
+pub(crate) fn username_check(username: &str) -> Result<(), RegistrationResponse>{
+    let first = match username.chars().next(){
+        None => return Err(RegistrationResponse::UserNameEmpty),
+        Some(first) => first
+    };
+    if username.len() as u64 > _profile_limits().max_username_length{
+        return Err(RegistrationResponse::UserNameTooLong)
+    }
+    if !username.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'){
+        return Err(RegistrationResponse::UserNameInvalidCharacters)
+    }
+    if first.is_ascii_digit(){
+        return Err(RegistrationResponse::UserNameStartsWithDigit)
+    }
+    if USERNAME_STATE.with(|state| state.borrow().is_reserved(&fold_username(username))){
+        return Err(RegistrationResponse::UserNameReserved)
+    }
+    Ok(())
+}
+
+#[update]
+#[candid_method(update)]
+pub fn add_reserved_username(username: String) -> UpdateStateResponse{
+    let caller = ic_cdk::caller();
+    if !is_this_caller_authority(&caller){
+        return UpdateStateResponse::Unauthorized
+    }
+    USERNAME_STATE.with(|state| state.borrow_mut().add_reserved(fold_username(&username)));
+    UpdateStateResponse::Success
+}
+
+#[update]
+#[candid_method(update)]
+pub fn remove_reserved_username(username: String) -> UpdateStateResponse{
+    let caller = ic_cdk::caller();
+    if !is_this_caller_authority(&caller){
+        return UpdateStateResponse::Unauthorized
+    }
+    USERNAME_STATE.with(|state| state.borrow_mut().remove_reserved(&fold_username(&username)));
+    UpdateStateResponse::Success
+}
+
+/// The reserved list in its folded form, without the built-in names.
+#[query]
+#[candid_method(query)]
+pub fn get_reserved_usernames() -> Vec<String>{
+    USERNAME_STATE.with(|state| state.borrow().reserved.iter().map(|reserved| reserved.clone()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::init_memory;
+
+    fn rejection(username: &str) -> Option<RegistrationResponse>{
+        username_check(username).err()
+    }
+
+    #[test]
+    fn usernames_follow_the_naming_rules() {
+        init_memory();
+        assert!(username_check("alice_92").is_ok());
+        assert!(matches!(rejection(""), Some(RegistrationResponse::UserNameEmpty)));
+        assert!(matches!(rejection("a_name_well_over_twenty"), Some(RegistrationResponse::UserNameTooLong)));
+        assert!(matches!(rejection("alice-92"), Some(RegistrationResponse::UserNameInvalidCharacters)));
+        assert!(matches!(rejection("ålice"), Some(RegistrationResponse::UserNameInvalidCharacters)));
+        assert!(matches!(rejection("92alice"), Some(RegistrationResponse::UserNameStartsWithDigit)));
+        assert!(matches!(rejection("SUPP0RT"), Some(RegistrationResponse::UserNameReserved)));
+    }
+
+    #[test]
+    fn reserved_names_are_matched_after_folding() {
+        init_memory();
+        assert_eq!(fold_username("Alice"), fold_username("a1ICE"));
+        assert_ne!(fold_username("alice"), fold_username("alicia"));
+        for (a, b) in [("mail", "mall"), ("bill", "bii"), ("alice", "allce"), ("beat", "b3at"), ("tate", "t4te")] {
+            assert_ne!(fold_username(a), fold_username(b));
+        }
+        USERNAME_STATE.with(|state| state.borrow_mut().add_reserved(fold_username("PostWall")));
+        assert!(matches!(rejection("p0stwa11"), Some(RegistrationResponse::UserNameReserved)));
+        USERNAME_STATE.with(|state| state.borrow_mut().remove_reserved(&fold_username("postwall")));
+        assert!(username_check("p0stwa11").is_ok());
+    }
+
+    /// Every spelling of `name` with any of its letters swapped for a
+    /// digit that folds into it.
+    fn confusable_spellings(name: &str) -> Vec<String>{
+        name.chars().fold(vec![String::new()], |spellings, c|{
+            let mut next: Vec<String> = spellings.iter().map(|spelling| format!("{spelling}{c}")).collect();
+            for (digit, _) in CONFUSABLE_DIGITS.iter().filter(|(_, letter)| *letter == c){
+                next.extend(spellings.iter().map(|spelling| format!("{spelling}{digit}")));
+            }
+            next
+        })
+    }
+
+    #[test]
+    fn every_confusable_spelling_of_a_built_in_name_is_reserved() {
+        init_memory();
+        for name in BUILT_IN_RESERVED_USERNAMES {
+            for spelling in confusable_spellings(name).into_iter().chain([name.to_uppercase()]) {
+                assert!(USERNAME_STATE.with(|state| state.borrow().is_reserved(&fold_username(&spelling))), "{spelling}");
+                assert!(username_check(&spelling).is_err(), "{spelling}");
+            }
+        }
+        assert!(confusable_spellings("support").contains(&"5upp0rt".to_string()));
+        assert!(confusable_spellings("moderator").contains(&"m0derat0r".to_string()));
+    }
+}
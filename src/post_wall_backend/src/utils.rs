@@ -1,5 +1,9 @@
 use ic_ledger_types::Subaccount;
 use candid::Principal;
+use unicode_normalization::{UnicodeNormalization, is_nfc};
+
+/// Most bytes a single scalar value takes in UTF-8.
+pub(crate) const MAX_UTF8_CHAR_LENGTH: u64 = 4;
 
 pub(crate) fn subaccount_generator(principal: &Principal) -> Subaccount{
     let mut subaccount = [0; 32];
@@ -7,4 +11,50 @@ pub(crate) fn subaccount_generator(principal: &Principal) -> Subaccount{
     subaccount[0] = slice.len() as u8;
     subaccount[1..slice.len() + 1].copy_from_slice(slice);
     Subaccount(subaccount)
-}
\ No newline at end of file
+}
+
+/// The length limits on names, topics and messages count in Unicode scalar
+/// values of NFC-normalized text, so a precomposed `é` and an `e` followed
+/// by a combining accent both count as one.
+pub(crate) fn text_length(text: &str) -> u64{
+    text.chars().count() as u64
+}
+
+/// Puts user-supplied text into NFC, the form it is checked and stored in.
+pub(crate) fn normalize_text(text: String) -> String{
+    if is_nfc(&text){
+        text
+    }else{
+        text.nfc().collect()
+    }
+}
+
+pub(crate) const NANOSECONDS_PER_DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+pub(crate) fn is_leap_year(year: u64) -> bool{
+    (year.is_multiple_of(4) && !year.is_multiple_of(100)) || year.is_multiple_of(400)
+}
+
+pub(crate) fn days_in_month(year: u64, month: u64) -> u64{
+    match month{
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31
+    }
+}
+
+/// The UTC calendar date of a timestamp in nanoseconds, as `(year, month, day)`.
+pub(crate) fn date_of(timestamp: u64) -> (u64, u64, u64){
+    // Days-to-civil conversion over 400-year eras, shifted so years start in March.
+    let days = timestamp / NANOSECONDS_PER_DAY + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10{ shifted_month + 3 }else{ shifted_month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2{ 1 }else{ 0 };
+    (year, month, day)
+}
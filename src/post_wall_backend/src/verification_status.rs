@@ -1,13 +1,16 @@
 use std::cell::RefCell;
 
-use candid::{Nat, CandidType, candid_method};
+use candid::{Nat, CandidType, candid_method, Deserialize, Principal};
 use ic_cdk_macros::*;
-use ic_stable_memory::derive::{StableType, AsFixedSizeBytes};
+use ic_stable_memory::{derive::{StableType, AsFixedSizeBytes}, collections::{SVec, SBTreeMap}};
 
-use crate::{response::UpdateStateResponse, init::is_this_caller_authority};
+use crate::{response::{UpdateStateResponse, UpgradeBadgeResponse, SetUserBadgeResponse, BadgeAuditLogResponse, RenewVerificationResponse}, init::is_this_caller_authority, registration::{get_verification_badge, get_address, _suspended_until, _change_verification_badge, _query_verification_expiry, _set_verification_expiry, _mark_expiry_notified, _expiring_verifications}, payment::{collect_payment, refund_payment, PaymentError}, rate_limit::_check_rate_limit, receipt::{_issue_receipt, ReceiptKind}, config::{_verification_period, _expiry_notice_period}, notification::{_notify, NotificationKind}};
 
 
-#[derive(CandidType, StableType, AsFixedSizeBytes)]
+/// Verification prices in e8s. `None` means the badge is free. Prices in an
+/// ICRC-1 token use the token's smallest unit, and there `None` means the
+/// badge cannot be paid for with that token.
+#[derive(CandidType, StableType, AsFixedSizeBytes, Clone)]
 pub struct VerificationCharge{
     pub regular_verification: Option<Nat>,
     pub plus_verification: Option<Nat>,
@@ -24,6 +27,24 @@ impl Default for VerificationCharge{
     }
 }
 
+impl VerificationCharge{
+    fn charge_for(&self, verification_badge: &VerificationBadge) -> Option<Nat>{
+        match verification_badge{
+            VerificationBadge::RegularVerified => self.regular_verification.clone(),
+            VerificationBadge::PlusVerified => self.plus_verification.clone(),
+            VerificationBadge::BusinessVerified => self.business_verifcation.clone(),
+        }
+    }
+
+    fn set_charge(&mut self, verification_badge: &VerificationBadge, new_fee: Option<Nat>){
+        match verification_badge{
+            VerificationBadge::RegularVerified => self.regular_verification = new_fee,
+            VerificationBadge::PlusVerified => self.plus_verification = new_fee,
+            VerificationBadge::BusinessVerified => self.business_verifcation = new_fee,
+        }
+    }
+}
+
 #[derive(CandidType, StableType, AsFixedSizeBytes, Default)]
 pub struct UserCount{
     pub regular_verified_user: u128,
@@ -37,16 +58,31 @@ impl UserCount{
     }
 }
 
-#[derive(CandidType, StableType, AsFixedSizeBytes, Clone)]
+#[derive(CandidType, StableType, AsFixedSizeBytes, Clone, Deserialize, PartialEq)]
 pub enum VerificationBadge{
     RegularVerified,
     PlusVerified,
     BusinessVerified,
 }
 
+/// One entry of the badge audit log. `amount_paid` and `block_index` are
+/// only set when the change was paid for through the ledger.
+#[derive(CandidType, StableType, AsFixedSizeBytes, Clone)]
+pub struct BadgeChange{
+    pub address: Principal,
+    pub from: VerificationBadge,
+    pub to: VerificationBadge,
+    pub changed_by: Principal,
+    pub amount_paid: Option<Nat>,
+    pub block_index: Option<u64>,
+    pub changed_at: u64,
+}
+
 thread_local! {
     pub static VERIFICATION_CHARGE: RefCell<VerificationCharge> = RefCell::default();
+    pub static TOKEN_VERIFICATION_CHARGE: RefCell<SBTreeMap<Principal, VerificationCharge>> = RefCell::new(SBTreeMap::new());
     pub static USER_COUNT: RefCell<UserCount> = RefCell::default();
+    pub static BADGE_AUDIT_LOG: RefCell<SVec<BadgeChange>> = RefCell::new(SVec::new());
 }
 
 pub(crate) fn _add_regular_user(){
@@ -91,6 +127,22 @@ pub(crate) fn _remove_business_verified_user(){
     })
 }
 
+pub(crate) fn _add_user(verification_badge: &VerificationBadge){
+    match verification_badge{
+        VerificationBadge::RegularVerified => _add_regular_user(),
+        VerificationBadge::PlusVerified => _add_plus_verfied_user(),
+        VerificationBadge::BusinessVerified => _add_business_verified_user(),
+    }
+}
+
+pub(crate) fn _remove_user(verification_badge: &VerificationBadge){
+    match verification_badge{
+        VerificationBadge::RegularVerified => _remove_regular_user(),
+        VerificationBadge::PlusVerified => _remove_plus_verfied_user(),
+        VerificationBadge::BusinessVerified => _remove_business_verified_user(),
+    }
+}
+
 pub(crate) fn _total_user_count() -> u128{
     USER_COUNT.with(|state| state.borrow().total_users())
 }
@@ -107,12 +159,59 @@ pub(crate) fn _business_verification_charge() -> Option<Nat>{
     VERIFICATION_CHARGE.with(|state| state.borrow().business_verifcation.clone())
 }
 
-pub(crate) fn _update_plus_verification_charge(new_fee: Nat){
-    VERIFICATION_CHARGE.with(|state| state.borrow_mut().plus_verification = Some(new_fee));
+pub(crate) fn _verification_charge(verification_badge: &VerificationBadge) -> Option<Nat>{
+    match verification_badge{
+        VerificationBadge::RegularVerified => _regular_verification_charge(),
+        VerificationBadge::PlusVerified => _plus_verification_charge(),
+        VerificationBadge::BusinessVerified => _business_verification_charge(),
+    }
+}
+
+pub(crate) fn _query_verification_charges() -> VerificationCharge{
+    VERIFICATION_CHARGE.with(|state| state.borrow().clone())
+}
+
+pub(crate) fn _set_verification_charge(verification_badge: &VerificationBadge, new_fee: Option<Nat>){
+    VERIFICATION_CHARGE.with(|state| state.borrow_mut().set_charge(verification_badge, new_fee))
+}
+
+/// The price of `verification_badge` when paying with `payment_token`, or
+/// `None` when the badge cannot be paid for with that token. ICP prices that
+/// are not set are free.
+pub(crate) fn _verification_charge_in(verification_badge: &VerificationBadge, payment_token: &Option<Principal>) -> Option<Nat>{
+    match payment_token{
+        None => Some(_verification_charge(verification_badge).unwrap_or_default()),
+        Some(ledger) => TOKEN_VERIFICATION_CHARGE.with(|state|{
+            state.borrow().get(ledger).and_then(|charge| charge.charge_for(verification_badge))
+        })
+    }
+}
+
+pub(crate) fn _set_token_verification_charge(ledger: Principal, verification_badge: &VerificationBadge, new_fee: Option<Nat>) -> bool{
+    TOKEN_VERIFICATION_CHARGE.with(|state|{
+        let state = &mut state.borrow_mut();
+        if let Some(mut charge) = state.get_mut(&ledger){
+            charge.set_charge(verification_badge, new_fee);
+            return true
+        }
+        let mut charge = VerificationCharge::default();
+        charge.set_charge(verification_badge, new_fee);
+        state.insert(ledger, charge).is_ok()
+    })
+}
+
+pub(crate) fn _is_payment_token(ledger: &Principal) -> bool{
+    TOKEN_VERIFICATION_CHARGE.with(|state| state.borrow().contains_key(ledger))
+}
+
+pub(crate) fn _remove_payment_token(ledger: &Principal){
+    TOKEN_VERIFICATION_CHARGE.with(|state| state.borrow_mut().remove(ledger));
 }
 
-pub(crate) fn _update_business_verification_charge(new_fee: Nat){
-    VERIFICATION_CHARGE.with(|state| state.borrow_mut().business_verifcation = Some(new_fee));
+pub(crate) fn _query_token_verification_charges() -> Vec<(Principal, VerificationCharge)>{
+    TOKEN_VERIFICATION_CHARGE.with(|state|{
+        state.borrow().iter().map(|(ledger, charge)| (*ledger, charge.clone())).collect()
+    })
 }
 
 pub(crate) fn _query_regular_verified_user_count() -> u128{
@@ -127,6 +226,76 @@ pub(crate) fn _query_business_verified_user_count() -> u128{
     USER_COUNT.with(|state| state.borrow().business_veried_user)
 }
 
+/// Paid badges expire after the configured verification period; regular
+/// verification never does.
+pub(crate) fn _verification_expiry(verification_badge: &VerificationBadge, now: u64) -> Option<u64>{
+    match verification_badge{
+        VerificationBadge::RegularVerified => None,
+        _ => Some(now.saturating_add(_verification_period())),
+    }
+}
+
+/// The expiry left by a change from `from` to `to`. A period only starts
+/// when a paid badge is taken up from regular verification; moving between
+/// paid badges keeps the running one, as only the price difference is paid.
+pub(crate) fn _expiry_after_change(from: &VerificationBadge, to: &VerificationBadge, current_expiry: Option<u64>, now: u64) -> Option<u64>{
+    match current_expiry{
+        Some(current_expiry) if *from != VerificationBadge::RegularVerified && *to != VerificationBadge::RegularVerified => Some(current_expiry),
+        _ => _verification_expiry(to, now)
+    }
+}
+
+/// Records the change in the audit log first, so a badge never changes
+/// without a matching log entry.
+fn _apply_badge_change(change: BadgeChange) -> bool{
+    let (address, from, to) = (change.address, change.from.clone(), change.to.clone());
+    let current_expiry = _query_verification_expiry(&address).flatten();
+    let expires_at = _expiry_after_change(&from, &to, current_expiry, change.changed_at);
+    let logged = BADGE_AUDIT_LOG.with(|state| state.borrow_mut().push(change).is_ok());
+    if !logged{
+        return false
+    }
+    _change_verification_badge(&address, to.clone(), expires_at);
+    _remove_user(&from);
+    _add_user(&to);
+    true
+}
+
+/// Downgrades every paid verification that has run out and notifies
+/// holders whose verification expires within the notice period.
+pub(crate) fn _expire_verifications(now: u64){
+    let notice_period = _expiry_notice_period();
+    for (address, expires_at, expiry_notified) in _expiring_verifications(){
+        if expires_at <= now{
+            let from = match get_verification_badge(&address){
+                None => continue,
+                Some(from) => from
+            };
+            let change = BadgeChange{
+                address,
+                from,
+                to: VerificationBadge::RegularVerified,
+                changed_by: ic_cdk::id(),
+                amount_paid: None,
+                block_index: None,
+                changed_at: now,
+            };
+            if _apply_badge_change(change){
+                _notify(address, NotificationKind::VerificationExpired);
+            }
+        }else if !expiry_notified && expires_at - now <= notice_period && _notify(address, NotificationKind::VerificationExpiringSoon{ expires_at }){
+            _mark_expiry_notified(&address);
+        }
+    }
+}
+
+fn _badge_audit_log(start: usize, limit: usize) -> Vec<BadgeChange>{
+    BADGE_AUDIT_LOG.with(|state|{
+        let state = state.borrow();
+        state.iter().skip(start).take(limit).map(|change| change.clone()).collect()
+    })
+}
+
 #[query]
 #[candid_method(query)]
 pub fn total_number_of_users() -> u128{
@@ -140,7 +309,7 @@ pub fn update_plus_verification_charge(new_fee: Nat) -> UpdateStateResponse{
     if !is_this_caller_authority(&caller){
         UpdateStateResponse::Unauthorized
     }else{
-        _update_plus_verification_charge(new_fee);
+        _set_verification_charge(&VerificationBadge::PlusVerified, Some(new_fee));
         UpdateStateResponse::Success
     }
 }
@@ -152,7 +321,240 @@ pub fn update_business_verification_charge(new_fee: Nat) -> UpdateStateResponse{
     if !is_this_caller_authority(&caller){
         UpdateStateResponse::Unauthorized
     }else{
-        _update_business_verification_charge(new_fee);
+        _set_verification_charge(&VerificationBadge::BusinessVerified, Some(new_fee));
+        UpdateStateResponse::Success
+    }
+}
+
+#[update]
+#[candid_method(update)]
+pub fn set_verification_charge(verification_badge: VerificationBadge, new_fee: Option<Nat>) -> UpdateStateResponse{
+    let caller = ic_cdk::caller();
+    if !is_this_caller_authority(&caller){
+        UpdateStateResponse::Unauthorized
+    }else{
+        _set_verification_charge(&verification_badge, new_fee);
+        UpdateStateResponse::Success
+    }
+}
+
+#[query]
+#[candid_method(query)]
+pub fn get_verification_charges() -> VerificationCharge{
+    _query_verification_charges()
+}
+
+#[update]
+#[candid_method(update)]
+pub fn set_token_verification_charge(ledger: Principal, verification_badge: VerificationBadge, new_fee: Option<Nat>) -> UpdateStateResponse{
+    let caller = ic_cdk::caller();
+    if !is_this_caller_authority(&caller){
+        UpdateStateResponse::Unauthorized
+    }else{
+        _set_token_verification_charge(ledger, &verification_badge, new_fee);
+        UpdateStateResponse::Success
+    }
+}
+
+#[update]
+#[candid_method(update)]
+pub fn remove_payment_token(ledger: Principal) -> UpdateStateResponse{
+    let caller = ic_cdk::caller();
+    if !is_this_caller_authority(&caller){
+        UpdateStateResponse::Unauthorized
+    }else{
+        _remove_payment_token(&ledger);
         UpdateStateResponse::Success
     }
-}
\ No newline at end of file
+}
+
+#[query]
+#[candid_method(query)]
+pub fn get_token_verification_charges() -> Vec<(Principal, VerificationCharge)>{
+    _query_token_verification_charges()
+}
+
+/// What moving from `from` to `to` costs in `payment_token`: the difference
+/// between the two charges, or nothing when moving down. `None` when `to`
+/// cannot be paid for with the token.
+fn _upgrade_charge(from: &VerificationBadge, to: &VerificationBadge, payment_token: &Option<Principal>) -> Option<Nat>{
+    let to_charge = _verification_charge_in(to, payment_token)?;
+    let from_charge = _verification_charge_in(from, payment_token).unwrap_or_default();
+    if to_charge > from_charge{
+        Some(to_charge - from_charge)
+    }else{
+        Some(Nat::from(0))
+    }
+}
+
+#[update]
+#[candid_method(update)]
+pub async fn upgrade_badge(target: VerificationBadge, payment_token: Option<Principal>) -> UpgradeBadgeResponse{
+    let caller = ic_cdk::caller();
+    if let Err(retry_after_ns) = _check_rate_limit(caller, "upgrade_badge"){
+        return UpgradeBadgeResponse::RateLimited{ retry_after_ns }
+    }
+    if let Some(until) = _suspended_until(&caller, ic_cdk::api::time()){
+        return UpgradeBadgeResponse::Suspended{ until }
+    }
+    let current = match get_verification_badge(&caller){
+        None => return UpgradeBadgeResponse::UserNotRegistered,
+        Some(current) => current
+    };
+    if current == target{
+        return UpgradeBadgeResponse::AlreadyHasBadge
+    }
+    let difference = match _upgrade_charge(&current, &target, &payment_token){
+        None => return UpgradeBadgeResponse::UnsupportedPaymentToken,
+        Some(difference) => difference
+    };
+    let block_index = match collect_payment(caller, &difference, payment_token).await{
+        Ok(block_index) => block_index,
+        Err(PaymentError::InsufficientDeposit) => return UpgradeBadgeResponse::VerificationAmountNotPaid,
+        Err(PaymentError::PaymentInProgress) => return UpgradeBadgeResponse::PaymentInProgress,
+        Err(_) => return UpgradeBadgeResponse::PaymentFailed,
+    };
+    // the badge or the charges may have changed while the ledger call was in flight
+    let from = match get_verification_badge(&caller){
+        None => return UpgradeBadgeResponse::UserNotRegistered,
+        Some(from) => from
+    };
+    if from == target || _upgrade_charge(&from, &target, &payment_token) != Some(difference.clone()){
+        let refunded = match block_index{
+            None => true,
+            Some(_) => refund_payment(caller, &difference, payment_token).await.is_ok()
+        };
+        if let (Some(block_index), false) = (block_index, refunded){
+            // keep the payment on record so the treasury can pay it back
+            _issue_receipt(caller, ReceiptKind::BadgeUpgrade, block_index, difference, payment_token, Some(target));
+        }
+        return UpgradeBadgeResponse::PriceChanged{ refunded }
+    }
+    if let Some(block_index) = block_index{
+        _issue_receipt(caller, ReceiptKind::BadgeUpgrade, block_index, difference.clone(), payment_token, Some(target.clone()));
+    }
+    let change = BadgeChange{
+        address: caller,
+        from,
+        to: target,
+        changed_by: caller,
+        amount_paid: block_index.map(|_| difference),
+        block_index,
+        changed_at: ic_cdk::api::time(),
+    };
+    if !_apply_badge_change(change){
+        return UpgradeBadgeResponse::FailedToAllocateMemory
+    }
+    UpgradeBadgeResponse::Success
+}
+
+#[update]
+#[candid_method(update)]
+pub fn set_user_badge(username: String, verification_badge: VerificationBadge) -> SetUserBadgeResponse{
+    let caller = ic_cdk::caller();
+    if !is_this_caller_authority(&caller){
+        return SetUserBadgeResponse::Unauthorized
+    }
+    let address = match get_address(&username){
+        None => return SetUserBadgeResponse::UserNotFound,
+        Some(address) => address
+    };
+    let from = match get_verification_badge(&address){
+        None => return SetUserBadgeResponse::UserNotFound,
+        Some(from) => from
+    };
+    let change = BadgeChange{
+        address,
+        from,
+        to: verification_badge,
+        changed_by: caller,
+        amount_paid: None,
+        block_index: None,
+        changed_at: ic_cdk::api::time(),
+    };
+    if !_apply_badge_change(change){
+        return SetUserBadgeResponse::FailedToAllocateMemory
+    }
+    SetUserBadgeResponse::Success
+}
+
+#[query]
+#[candid_method(query)]
+pub fn get_badge_audit_log(start: u64, limit: u64) -> BadgeAuditLogResponse{
+    let caller = ic_cdk::caller();
+    if !is_this_caller_authority(&caller){
+        return BadgeAuditLogResponse::Unauthorized
+    }
+    BadgeAuditLogResponse::Success(_badge_audit_log(start as usize, limit as usize))
+}
+
+#[update]
+#[candid_method(update)]
+pub async fn renew_verification(payment_token: Option<Principal>) -> RenewVerificationResponse{
+    let caller = ic_cdk::caller();
+    if let Some(until) = _suspended_until(&caller, ic_cdk::api::time()){
+        return RenewVerificationResponse::Suspended{ until }
+    }
+    let verification_badge = match get_verification_badge(&caller){
+        None => return RenewVerificationResponse::UserNotRegistered,
+        Some(verification_badge) => verification_badge
+    };
+    if verification_badge == VerificationBadge::RegularVerified{
+        return RenewVerificationResponse::NothingToRenew
+    }
+    let charge = match _verification_charge_in(&verification_badge, &payment_token){
+        None => return RenewVerificationResponse::UnsupportedPaymentToken,
+        Some(charge) => charge
+    };
+    match collect_payment(caller, &charge, payment_token).await{
+        Ok(None) => {},
+        Ok(Some(block_index)) => {
+            _issue_receipt(caller, ReceiptKind::Renewal, block_index, charge, payment_token, Some(verification_badge));
+        },
+        Err(PaymentError::InsufficientDeposit) => return RenewVerificationResponse::VerificationAmountNotPaid,
+        Err(PaymentError::PaymentInProgress) => return RenewVerificationResponse::PaymentInProgress,
+        Err(_) => return RenewVerificationResponse::PaymentFailed,
+    }
+    let now = ic_cdk::api::time();
+    let current_expiry = match _query_verification_expiry(&caller){
+        None => return RenewVerificationResponse::UserNotRegistered,
+        Some(current_expiry) => current_expiry.unwrap_or(now)
+    };
+    let expires_at = current_expiry.max(now).saturating_add(_verification_period());
+    _set_verification_expiry(&caller, Some(expires_at));
+    RenewVerificationResponse::Success{ expires_at }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{principal, init_memory};
+
+    #[test]
+    fn switching_between_paid_badges_keeps_the_running_period() {
+        init_memory();
+        let period = _verification_period();
+        let expires_at = Some(period);
+        // a business account near expiry steps down to plus for free and back up for the difference
+        let downgraded = _expiry_after_change(&VerificationBadge::BusinessVerified, &VerificationBadge::PlusVerified, expires_at, period - 1);
+        assert_eq!(downgraded, expires_at);
+        let upgraded = _expiry_after_change(&VerificationBadge::PlusVerified, &VerificationBadge::BusinessVerified, downgraded, period - 1);
+        assert_eq!(upgraded, expires_at);
+        assert_eq!(_expiry_after_change(&VerificationBadge::BusinessVerified, &VerificationBadge::RegularVerified, upgraded, period - 1), None);
+        assert_eq!(_expiry_after_change(&VerificationBadge::RegularVerified, &VerificationBadge::PlusVerified, None, 10), Some(10 + period));
+    }
+
+    #[test]
+    fn upgrade_charge_is_recomputed_from_the_current_charges() {
+        init_memory();
+        _set_verification_charge(&VerificationBadge::PlusVerified, Some(Nat::from(100)));
+        _set_verification_charge(&VerificationBadge::BusinessVerified, Some(Nat::from(300)));
+        let quoted = _upgrade_charge(&VerificationBadge::PlusVerified, &VerificationBadge::BusinessVerified, &None);
+        assert_eq!(quoted, Some(Nat::from(200)));
+        assert_eq!(_upgrade_charge(&VerificationBadge::BusinessVerified, &VerificationBadge::PlusVerified, &None), Some(Nat::from(0)));
+        // an authority raising the charge mid-payment makes the quote stale
+        _set_verification_charge(&VerificationBadge::BusinessVerified, Some(Nat::from(500)));
+        assert_ne!(_upgrade_charge(&VerificationBadge::PlusVerified, &VerificationBadge::BusinessVerified, &None), quoted);
+        assert_eq!(_upgrade_charge(&VerificationBadge::RegularVerified, &VerificationBadge::PlusVerified, &Some(principal(50))), None);
+    }
+}